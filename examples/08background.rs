@@ -8,7 +8,7 @@ fn main() {
         (0.0, 1.0, 0.0).into()
     );
 
-    let mut scene: Scene = Vec::new();
+    let mut scene = Scene::new();
     let mut lights: SceneLights = Vec::new();
 
     scene.push(Box::new(Plane::new(
@@ -1,73 +1,1052 @@
-use image::{DynamicImage, GenericImageView};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
 
-use crate::{math::Vec3, INV_TAU, INV_PI, color::Color, surface::{Surface, HitInfo}, materials::Material};
+use image::{DynamicImage, ImageError, ImageFormat, codecs::hdr::HdrDecoder, error::{ParameterError, ParameterErrorKind}};
+
+use crate::{
+    math::{Vec3, Ray, RayKind},
+    INV_TAU, INV_PI,
+    color::Color,
+    surface::{Surface, Scene, HitInfo, Intersection},
+    materials::Material,
+    light::{Light, LightSample, SceneLights},
+    sampling::Distribution2D,
+    tonemap::srgb_to_linear,
+};
+
+/// Upper bound for the resolution of the distribution that [`EnvironmentLight`] samples directions from, large maps
+/// are averaged down to it.
+const MAX_DISTRIBUTION_SIZE: (u32, u32) = (1024, 512);
+
+/// Resolution of the distribution light sampling uses for procedural environments, they are expected to be smooth.
+const PROCEDURAL_DISTRIBUTION_SIZE: (usize, usize) = (64, 32);
+
+/// Number of stratified samples shading takes from the environment unless configured otherwise.
+const DEFAULT_ENVIRONMENT_SAMPLES: usize = 16;
 
 pub struct BackgroundSurface {
-    background: Box<dyn Material>,
+    background: Arc<Background>,
 }
 
 impl BackgroundSurface {
+    /// Loads the background image, falling back to a black background if it can't be read.
     pub fn new(path: &str) -> Self {
-        let image = match image::open(path) {
-            Ok(image) => image,
-            Err(_) => {
-                let mut image = image::RgbImage::new(1, 1);
-                image.put_pixel(0, 0, image::Rgb([0, 0, 0]));
-                image.into()
-            },
+        Self::open(path).unwrap_or_else(|_| {
+            let mut image = image::RgbImage::new(1, 1);
+            image.put_pixel(0, 0, image::Rgb([0, 0, 0]));
+            Self::from_image(image.into())
+        })
+    }
+
+    /// Loads an equirectangular environment map. Radiance `.hdr` and OpenEXR `.exr` files keep their full floating
+    /// point radiance, other formats are taken to be sRGB encoded.
+    pub fn open(path: &str) -> Result<Self, ImageError> {
+        Self::open_with(path, Projection::Equirectangular)
+    }
+
+    /// Loads an environment map stored with the given projection.
+    pub fn open_with(path: &str, projection: Projection) -> Result<Self, ImageError> {
+        Self::from_image_with(open_image(path)?, projection)
+    }
+
+    /// Loads a cubemap from six square images in the order +x, -x, +y, -y, +z, -z, laid out like the faces of
+    /// [`Projection::Cubemap`].
+    pub fn open_cubemap(paths: [&str; 6]) -> Result<Self, ImageError> {
+        let mut faces = Vec::with_capacity(6);
+        for path in paths {
+            faces.push(MipLevel::from_image(open_image(path)?));
+        }
+        let faces: [MipLevel; 6] = faces.try_into().expect("one image per face");
+        if faces.iter().any(|face| face.width != faces[0].width || face.height != faces[0].height) {
+            return Err(dimension_mismatch())
+        }
+        Ok(Self::from_level(resample_cubemap(&faces)))
+    }
+
+    pub fn from_image(image: DynamicImage) -> Self {
+        Self::from_level(MipLevel::from_image(image))
+    }
+
+    pub fn from_image_with(image: DynamicImage, projection: Projection) -> Result<Self, ImageError> {
+        let image = MipLevel::from_image(image);
+        let level = match projection {
+            Projection::Equirectangular => image,
+            Projection::Cubemap => resample_cubemap(&split_cross(&image)?),
+            Projection::Angular | Projection::MirrorBall => {
+                let size = image.width.min(image.height);
+                resample(size, size / 2, |direction| {
+                    let (s, t) = projection.disk_position(direction);
+                    // The disk is inscribed into the center of the image.
+                    let (x0, y0) = ((image.width - size) as f64 * 0.5, (image.height - size) as f64 * 0.5);
+                    let u = (x0 + (s + 1.0) * 0.5 * size as f64) / image.width as f64;
+                    let v = (y0 + (t + 1.0) * 0.5 * size as f64) / image.height as f64;
+                    image.clamped_bilinear(u, v)
+                })
+            }
         };
+        Ok(Self::from_level(level))
+    }
+
+    /// Bakes a procedural environment into a map of the given size, `radiance` gives the radiance that a ray
+    /// travelling in a direction sees.
+    pub fn from_fn(width: u32, height: u32, radiance: impl Fn(Vec3) -> Color) -> Self {
+        Self::from_level(resample(width, height, |direction| {
+            let color = radiance(direction);
+            [color.r as f32, color.g as f32, color.b as f32]
+        }))
+    }
+
+    /// Background with a procedural environment, like a [`SolidBackground`] or a [`GradientBackground`].
+    pub fn from_environment<E: Environment + 'static>(environment: E) -> Self {
+        Self::from_source(Source::Procedural(Arc::new(environment)))
+    }
+
+    fn from_level(level: MipLevel) -> Self {
+        Self::from_source(Source::Map(EnvironmentMap::new(level)))
+    }
+
+    fn from_source(source: Source) -> Self {
+        Self {
+            background: Arc::new(Background { source, intensity: 1.0, rotation: 0.0, visibility: Visibility::default() })
+        }
+    }
+
+    /// Scales the radiance of the map.
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        Arc::make_mut(&mut self.background).intensity = intensity;
+        self
+    }
+
+    /// Turns the map around the up axis by `angle` radians, counter-clockwise seen from above.
+    pub fn with_rotation(mut self, angle: f64) -> Self {
+        Arc::make_mut(&mut self.background).rotation = angle;
+        self
+    }
+
+    /// Sets how the map is interpolated between texels, procedural environments ignore it.
+    pub fn with_filter(mut self, filter: EnvironmentFilter) -> Self {
+        if let Source::Map(map) = &mut Arc::make_mut(&mut self.background).source {
+            map.filter = filter;
+        }
+        self
+    }
+
+    /// Sets which kinds of rays see the background, rays it is hidden from pass on to the next background.
+    pub fn with_visibility(mut self, visibility: Visibility) -> Self {
+        Arc::make_mut(&mut self.background).visibility = visibility;
+        self
+    }
+
+    pub fn visibility(&self) -> Visibility {
+        self.background.visibility
+    }
+
+    /// Light that lights the scene with the background, add it to the scene lights next to the surface.
+    pub fn light(&self) -> EnvironmentLight {
+        EnvironmentLight::new(self.background.clone())
+    }
+}
+
+/// Opens an image keeping the floating point radiance of Radiance `.hdr` files, which `image::open` would quantize to
+/// 8 bits.
+fn open_image(path: &str) -> Result<DynamicImage, ImageError> {
+    if ImageFormat::from_path(path).ok() != Some(ImageFormat::Hdr) {
+        return image::open(path)
+    }
+    let decoder = HdrDecoder::new(BufReader::new(File::open(Path::new(path))?))?;
+    let metadata = decoder.metadata();
+    let texels = decoder.read_image_hdr()?.into_iter().flat_map(|pixel| pixel.0).collect();
+    let image = image::Rgb32FImage::from_raw(metadata.width, metadata.height, texels).expect("one pixel per texel");
+    Ok(image.into())
+}
+
+/// How the directions around the scene are laid out in an environment map image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Projection {
+    /// Latitude-longitude map, the full circle of longitudes from left to right and straight up in the top row.
+    #[default]
+    Equirectangular,
+    /// Six faces of a cube in a 4x3 horizontal or 3x4 vertical cross. The middle row of the horizontal cross shows
+    /// -x, -z, +x and +z from left to right, each seen from the center with +y up, with +y above and -y below the
+    /// -z face. The vertical cross has +z below -y, upside down.
+    Cubemap,
+    /// Angular map of a light probe, -z in the center and the angle from it growing linearly with the radius up to
+    /// +z on the rim.
+    Angular,
+    /// Photo of a mirror ball taken from far along +z, so +z is in the center and -z on the rim.
+    MirrorBall,
+}
+
+impl Projection {
+    /// Position in `[-1, 1]^2` within the disk of an angular or mirror ball map, with y growing downwards.
+    fn disk_position(self, direction: Vec3) -> (f64, f64) {
+        let direction = direction.normalize();
+        let radius = match self {
+            Projection::Angular => (-direction.z).clamp(-1.0, 1.0).acos() * INV_PI,
+            // The ball reflects `direction` where its normal is halfway between it and the camera along +z.
+            _ => ((1.0 - direction.z) * 0.5).max(0.0).sqrt(),
+        };
+        let length = (direction.x * direction.x + direction.y * direction.y).sqrt();
+        if length <= 0.0 {
+            return (radius, 0.0)
+        }
+        (direction.x / length * radius, -direction.y / length * radius)
+    }
+}
+
+/// Converts a map with another projection into an equirectangular one of the given size, `radiance` looks up the
+/// source map in a direction.
+fn resample(width: u32, height: u32, radiance: impl Fn(Vec3) -> [f32; 3]) -> MipLevel {
+    let (width, height) = (width.max(1), height.max(1));
+    let mut texels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let u = (x as f64 + 0.5) / width as f64;
+            let v = (y as f64 + 0.5) / height as f64;
+            texels.push(radiance(equirectangular_direction(u, v)));
+        }
+    }
+    MipLevel { texels, width, height }
+}
+
+/// Cube faces in the order +x, -x, +y, -y, +z, -z.
+fn resample_cubemap(faces: &[MipLevel; 6]) -> MipLevel {
+    let size = faces[0].width;
+    resample(4 * size, 2 * size, |direction| {
+        let (face, s, t) = cube_face(direction);
+        faces[face].clamped_bilinear((s + 1.0) * 0.5, (t + 1.0) * 0.5)
+    })
+}
+
+/// Face that `direction` points at and the position in it in `[-1, 1]^2`, with y growing downwards.
+fn cube_face(direction: Vec3) -> (usize, f64, f64) {
+    let Vec3 { x, y, z } = direction;
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    if ax >= ay && ax >= az {
+        if x > 0.0 { (0, z / ax, -y / ax) } else { (1, -z / ax, -y / ax) }
+    } else if ay >= az {
+        if y > 0.0 { (2, x / ay, -z / ay) } else { (3, x / ay, z / ay) }
+    } else if z > 0.0 {
+        (4, -x / az, -y / az)
+    } else {
+        (5, x / az, -y / az)
+    }
+}
+
+/// Cuts the faces out of a cubemap cross, in the order +x, -x, +y, -y, +z, -z.
+fn split_cross(image: &MipLevel) -> Result<[MipLevel; 6], ImageError> {
+    // Cells of the faces in the cross and whether they are stored upside down.
+    let (size, cells) = if image.width * 3 == image.height * 4 {
+        (image.width / 4, [(2, 1, false), (0, 1, false), (1, 0, false), (1, 2, false), (3, 1, false), (1, 1, false)])
+    } else if image.width * 4 == image.height * 3 {
+        (image.width / 3, [(2, 1, false), (0, 1, false), (1, 0, false), (1, 2, false), (1, 3, true), (1, 1, false)])
+    } else {
+        return Err(dimension_mismatch())
+    };
+
+    Ok(cells.map(|(column, row, flipped)| {
+        let mut texels = Vec::with_capacity((size * size) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let (x, y) = if flipped { (size - 1 - x, size - 1 - y) } else { (x, y) };
+                texels.push(image.texels[((row * size + y) * image.width + column * size + x) as usize]);
+            }
+        }
+        MipLevel { texels, width: size, height: size }
+    }))
+}
+
+fn dimension_mismatch() -> ImageError {
+    ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch))
+}
+
+/// Position in an equirectangular map that a ray travelling in `direction` sees. Looking along -z the center of
+/// the map is straight ahead and +x to the right of it, like a panorama seen from the inside.
+fn equirectangular_uv(direction: Vec3) -> (f64, f64) {
+    let direction = direction.normalize();
+    let u = direction.x.atan2(-direction.z) * INV_TAU + 0.5;
+    let v = ((-direction.y).clamp(-1.0, 1.0).asin() + std::f64::consts::FRAC_PI_2) * INV_PI;
+    (u, v)
+}
+
+/// Inverse of [`equirectangular_uv`].
+fn equirectangular_direction(u: f64, v: f64) -> Vec3 {
+    let phi = std::f64::consts::TAU * u - std::f64::consts::PI;
+    let (sin_theta, cos_theta) = (std::f64::consts::PI * v).sin_cos();
+    Vec3::new(sin_theta * phi.sin(), cos_theta, -sin_theta * phi.cos())
+}
+
+/// How the environment map is interpolated between texels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnvironmentFilter {
+    /// The closest texel, blocky when the map is magnified.
+    Nearest,
+    /// Linear interpolation between the four closest texels.
+    #[default]
+    Bilinear,
+    /// Catmull-Rom interpolation over the closest 4x4 texels, sharper than bilinear.
+    Bicubic,
+}
+
+/// One level of the mip pyramid, texels in linear radiance row by row.
+#[derive(Debug, Clone)]
+struct MipLevel {
+    texels: Vec<[f32; 3]>,
+    width: u32,
+    height: u32,
+}
+
+impl MipLevel {
+    fn from_image(image: DynamicImage) -> Self {
         let width = image.width();
         let height = image.height();
-        Self {
-            background: Box::new(Background { image, width, height })
+        let is_float = matches!(image, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_));
+        let texels = image.into_rgb32f().pixels().map(|pixel| {
+            // Integer images are sRGB encoded, the renderer works in linear radiance.
+            if is_float { pixel.0 } else { pixel.0.map(|c| srgb_to_linear(c as f64) as f32) }
+        }).collect();
+        Self { texels, width, height }
+    }
+
+    /// Averages blocks of 2x2 texels, an odd last column or row is folded into the block before it.
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = vec![[0.0f32; 3]; (width * height) as usize];
+        let mut counts = vec![0u32; (width * height) as usize];
+        for y in 0..self.height {
+            let row = (y / 2).min(height - 1);
+            for x in 0..self.width {
+                let cell = (row * width + (x / 2).min(width - 1)) as usize;
+                let texel = self.texels[(y * self.width + x) as usize];
+                for (sum, value) in texels[cell].iter_mut().zip(texel) {
+                    *sum += value;
+                }
+                counts[cell] += 1;
+            }
+        }
+        for (texel, count) in texels.iter_mut().zip(counts) {
+            *texel = texel.map(|value| value / count as f32);
         }
+        Self { texels, width, height }
     }
+
+    /// Texel with the longitude wrapped around and the latitude clamped at the poles.
+    fn texel(&self, x: i64, y: i64) -> [f32; 3] {
+        let x = x.rem_euclid(self.width as i64);
+        let y = y.clamp(0, self.height as i64 - 1);
+        self.texels[(y * self.width as i64 + x) as usize]
+    }
+
+    /// Bilinear lookup that clamps at all edges instead of wrapping, for images that are not equirectangular.
+    fn clamped_bilinear(&self, u: f64, v: f64) -> [f32; 3] {
+        let x = (u * self.width as f64 - 0.5).clamp(0.0, (self.width - 1) as f64);
+        let y = (v * self.height as f64 - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (x1, y1) = ((x0 + 1.0).min((self.width - 1) as f64), (y0 + 1.0).min((self.height - 1) as f64));
+        let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+        let texel = |x: f64, y: f64| self.texels[(y as u32 * self.width + x as u32) as usize];
+        let (a, b, c, d) = (texel(x0, y0), texel(x1, y0), texel(x0, y1), texel(x1, y1));
+        [0, 1, 2].map(|i| (a[i] * (1.0 - fx) + b[i] * fx) * (1.0 - fy) + (c[i] * (1.0 - fx) + d[i] * fx) * fy)
+    }
+
+    fn lookup(&self, u: f64, v: f64, filter: EnvironmentFilter) -> Color {
+        let [r, g, b] = match filter {
+            EnvironmentFilter::Nearest => {
+                self.texel((u * self.width as f64).floor() as i64, (v * self.height as f64).floor() as i64)
+            }
+            EnvironmentFilter::Bilinear => self.interpolate(u, v, |t| [1.0 - t, t]),
+            EnvironmentFilter::Bicubic => self.interpolate(u, v, catmull_rom),
+        };
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    /// Weighted sum over the `N` x `N` texels around `(u, v)`, `weights` gives the weights along one axis from the
+    /// fractional position between the two middle texels.
+    fn interpolate<const N: usize>(&self, u: f64, v: f64, weights: impl Fn(f64) -> [f64; N]) -> [f32; 3] {
+        // Texel centers sit at half integer coordinates.
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (wx, wy) = (weights(x - x0), weights(y - y0));
+        let offset = N as i64 / 2 - 1;
+
+        let mut sum = [0.0f64; 3];
+        for (j, wy) in wy.iter().enumerate() {
+            for (i, wx) in wx.iter().enumerate() {
+                let texel = self.texel(x0 as i64 + i as i64 - offset, y0 as i64 + j as i64 - offset);
+                for (sum, value) in sum.iter_mut().zip(texel) {
+                    *sum += wx * wy * value as f64;
+                }
+            }
+        }
+        // The negative lobes of the cubic can overshoot below zero next to bright texels.
+        sum.map(|value| value.max(0.0) as f32)
+    }
+}
+
+/// Catmull-Rom weights of the four texels around a point `t` of the way between the middle two.
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+/// Radiance arriving from infinitely far away, what rays that leave the scene see. Implement it for procedural
+/// backgrounds and wrap them with [`BackgroundSurface::from_environment`].
+pub trait Environment: Send + Sync {
+    /// Radiance arriving along a ray travelling in `direction`, averaged over a cone of directions `spread` radians
+    /// wide.
+    fn radiance(&self, direction: Vec3, spread: f64) -> Color;
 }
 
+/// The same radiance from every direction.
+#[derive(Debug, Clone, Copy)]
+pub struct SolidBackground {
+    color: Color,
+}
+
+impl SolidBackground {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Environment for SolidBackground {
+    fn radiance(&self, _direction: Vec3, _spread: f64) -> Color {
+        self.color
+    }
+}
+
+/// Linear blend from `bottom` straight down to `top` straight up.
+#[derive(Debug, Clone, Copy)]
+pub struct GradientBackground {
+    bottom: Color,
+    top: Color,
+}
+
+impl GradientBackground {
+    pub fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Environment for GradientBackground {
+    fn radiance(&self, direction: Vec3, _spread: f64) -> Color {
+        let t = (direction.normalize().y * 0.5 + 0.5).clamp(0.0, 1.0);
+        self.bottom * (1.0 - t) + self.top * t
+    }
+}
+
+/// Separate gradients for the sky, from `sky_horizon` to `zenith`, and the ground, from `ground_horizon` to
+/// `nadir`, that meet at the horizon.
+#[derive(Debug, Clone, Copy)]
+pub struct HemisphereBackground {
+    zenith: Color,
+    sky_horizon: Color,
+    ground_horizon: Color,
+    nadir: Color,
+}
+
+impl HemisphereBackground {
+    pub fn new(zenith: Color, sky_horizon: Color, ground_horizon: Color, nadir: Color) -> Self {
+        Self { zenith, sky_horizon, ground_horizon, nadir }
+    }
+}
+
+impl Environment for HemisphereBackground {
+    fn radiance(&self, direction: Vec3, _spread: f64) -> Color {
+        let y = direction.normalize().y.clamp(-1.0, 1.0);
+        if y >= 0.0 {
+            self.sky_horizon * (1.0 - y) + self.zenith * y
+        } else {
+            self.ground_horizon * (1.0 + y) + self.nadir * -y
+        }
+    }
+}
+
+/// Kinds of rays that see a background, see [`RayKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub reflections: bool,
+    /// Light gathered by diffuse and glossy surfaces, leave out [`BackgroundSurface::light`] as well when unset.
+    pub lighting: bool,
+}
+
+impl Visibility {
+    pub fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Reflection => self.reflections,
+            RayKind::Lighting => self.lighting,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self { camera: true, reflections: true, lighting: true }
+    }
+}
+
+/// Equirectangular environment map in linear radiance, the top row of the image is straight up.
 #[derive(Debug, Clone)]
-struct Background {
-    image: DynamicImage,
+struct EnvironmentMap {
+    /// Mip pyramid, the full resolution map first and every following level half the size down to a single row.
+    levels: Vec<MipLevel>,
     width: u32,
     height: u32,
+    filter: EnvironmentFilter,
+}
+
+impl EnvironmentMap {
+    fn new(level: MipLevel) -> Self {
+        let (width, height) = (level.width, level.height);
+        let mut levels = vec![level];
+        while let Some(level) = levels.last().filter(|level| level.height > 1) {
+            levels.push(level.downsample());
+        }
+        Self { levels, width, height, filter: EnvironmentFilter::default() }
+    }
+
+    /// Radiance at `(u, v)` averaged over a cone of directions `spread` radians wide.
+    fn lookup(&self, u: f64, v: f64, spread: f64) -> Color {
+        // Pick the levels whose texels are about as wide as the cone, a texel of the full map spans PI / height
+        // radians of latitude. Blending between the two closest levels hides the switch from one to the next.
+        let level = (spread * self.height as f64 * INV_PI).max(1.0).log2().min((self.levels.len() - 1) as f64);
+        let lower = level.floor() as usize;
+        let color = self.levels[lower].lookup(u, v, self.filter);
+        let blend = level - lower as f64;
+        if blend > 0.0 {
+            return color * (1.0 - blend) + self.levels[lower + 1].lookup(u, v, self.filter) * blend
+        }
+        color
+    }
+
+    /// Luminance of the map averaged down to at most [`MAX_DISTRIBUTION_SIZE`], weighted by the solid angle of the
+    /// rows. Returns the values row by row and the resolution.
+    fn luminance_distribution(&self) -> (Vec<f64>, usize, usize) {
+        let width = self.width.min(MAX_DISTRIBUTION_SIZE.0) as usize;
+        let height = self.height.min(MAX_DISTRIBUTION_SIZE.1) as usize;
+        let mut func = vec![0.0; width * height];
+        let mut counts = vec![0usize; width * height];
+        for y in 0..self.height {
+            // Rows near the poles cover less solid angle.
+            let sin_theta = (std::f64::consts::PI * (y as f64 + 0.5) / self.height as f64).sin();
+            let row = y as usize * height / self.height as usize;
+            for x in 0..self.width {
+                let cell = row * width + x as usize * width / self.width as usize;
+                let [r, g, b] = self.levels[0].texel(x as i64, y as i64);
+                func[cell] += Color::new(r as f64, g as f64, b as f64).luminance() * sin_theta;
+                counts[cell] += 1;
+            }
+        }
+        for (value, count) in func.iter_mut().zip(counts) {
+            *value /= count.max(1) as f64;
+        }
+        (func, width, height)
+    }
+}
+
+/// What a background shows.
+#[derive(Clone)]
+enum Source {
+    Map(EnvironmentMap),
+    Procedural(Arc<dyn Environment>),
+}
+
+/// The environment of a [`BackgroundSurface`] with the settings shared by all sources.
+#[derive(Clone)]
+struct Background {
+    source: Source,
+    intensity: f64,
+    /// Rotation around the up axis in radians.
+    rotation: f64,
+    visibility: Visibility,
 }
 
 impl Background {
-    pub fn sample(&self, vec: Vec3) -> Color {
-        let vec = vec.normalize();
-        let u = ((vec.x / vec.z).atan() + std::f64::consts::PI) * INV_TAU;
-        let v = (vec.y.asin() + std::f64::consts::FRAC_PI_2) * INV_PI;
+    /// `direction` in the frame of the unrotated environment.
+    fn to_local(&self, direction: Vec3) -> Vec3 {
+        let (sin, cos) = self.rotation.sin_cos();
+        // Rotating the environment one way is rotating the direction the other way.
+        Vec3::new(direction.x * cos - direction.z * sin, direction.y, direction.x * sin + direction.z * cos)
+    }
+
+    /// Position in the equirectangular parametrization, which light sampling works in, that a ray travelling in
+    /// `direction` sees.
+    fn uv(&self, direction: Vec3) -> (f64, f64) {
+        equirectangular_uv(self.to_local(direction))
+    }
 
-        let x = (self.width as f64 * u) as u32;
-        let y = (self.height as f64 * v) as u32;
+    /// Inverse of [`Background::uv`].
+    fn direction(&self, u: f64, v: f64) -> Vec3 {
+        let Vec3 { x, y, z } = equirectangular_direction(u, v);
+        let (sin, cos) = self.rotation.sin_cos();
+        Vec3::new(x * cos + z * sin, y, z * cos - x * sin)
+    }
+
+    /// Radiance arriving from the environment along a ray travelling in `direction`, averaged over a cone of
+    /// directions `spread` radians wide.
+    pub fn radiance(&self, direction: Vec3, spread: f64) -> Color {
+        let color = match &self.source {
+            Source::Map(map) => {
+                let (u, v) = self.uv(direction);
+                map.lookup(u, v, spread)
+            }
+            Source::Procedural(environment) => environment.radiance(self.to_local(direction), spread),
+        };
+        color * self.intensity
+    }
 
-        self.image.get_pixel(x, y).0.into()
+    /// Luminance over the equirectangular parametrization weighted by solid angle, row by row, and its resolution.
+    fn luminance_distribution(&self) -> (Vec<f64>, usize, usize) {
+        match &self.source {
+            Source::Map(map) => map.luminance_distribution(),
+            Source::Procedural(environment) => {
+                let (width, height) = PROCEDURAL_DISTRIBUTION_SIZE;
+                let mut func = Vec::with_capacity(width * height);
+                for y in 0..height {
+                    let v = (y as f64 + 0.5) / height as f64;
+                    let sin_theta = (std::f64::consts::PI * v).sin();
+                    for x in 0..width {
+                        let u = (x as f64 + 0.5) / width as f64;
+                        func.push(environment.radiance(equirectangular_direction(u, v), 0.0).luminance() * sin_theta);
+                    }
+                }
+                (func, width, height)
+            }
+        }
     }
 }
 
 impl Material for Background {
-    fn calc_mat(&self, _prev_ray: &crate::math::Ray, _hit_position: Vec3, hit_normal: Vec3, _scene: &crate::surface::Scene, _lights: Option<&crate::light::SceneLights>, _bounce_count: usize) -> Option<crate::surface::HitInfo> {
-        let color = self.sample(hit_normal);
+    fn calc_mat(&self, prev_ray: &Ray, _hit_position: Vec3, hit_normal: Vec3, _scene: &Scene, _lights: Option<&SceneLights>, _bounce_count: usize) -> Option<HitInfo> {
+        let color = self.radiance(prev_ray.direction, prev_ray.spread);
         Some(HitInfo::new(hit_normal*f64::INFINITY, hit_normal).tint(color))
     }
+
+    fn emitted(&self, wo: Vec3, _normal: Vec3) -> Color {
+        self.radiance(-wo, 0.0)
+    }
+
+    fn emitted_along(&self, ray: &Ray, _normal: Vec3) -> Color {
+        self.radiance(ray.direction, ray.spread)
+    }
 }
 
 impl Surface for BackgroundSurface {
-    fn get_normal(&self, hit: &Vec3) -> Vec3 {
-        -hit.normalize()
+    fn get_material(&self) -> &dyn Material {
+        self.background.as_ref()
     }
 
-    fn get_material(&self) -> &Box<dyn crate::materials::Material> {
-        &self.background
+    fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<Intersection> {
+        if max_distance >= f64::INFINITY && self.background.visibility.sees(ray.kind) {
+            return Some(Intersection { t: f64::INFINITY, position: ray.direction, normal: -ray.direction, uv: None, surface: 0 })
+        }
+        None
     }
 
-    fn surface_hit(&self, ray: &crate::math::Ray, min_distance: f64) -> Option<Vec3> {
-        if min_distance >= f64::INFINITY {
-            return Some(ray.direction)
+    fn shadow_hit(&self, _ray: &Ray, _light_dist: f64) -> bool {
+        false
+    }
+}
+
+/// Image based lighting from the environment of a [`BackgroundSurface`].
+///
+/// Directions are importance sampled in proportion to the luminance of the environment, so bright spots like the sun get
+/// most of the samples.
+pub struct EnvironmentLight {
+    background: Arc<Background>,
+    distribution: Distribution2D,
+    /// Mean radiance of the map, for treating the environment as a single light.
+    average: Color,
+    samples: usize,
+}
+
+impl EnvironmentLight {
+    fn new(background: Arc<Background>) -> Self {
+        let (func, width, height) = background.luminance_distribution();
+        let distribution = Distribution2D::new(&func, width, height);
+
+        // Prefiltered to the size of the cells of the distribution, so a coarse grid still sees every texel.
+        let spread = std::f64::consts::PI / height as f64;
+        let mut average: Color = 0.into();
+        let mut total_weight = 0.0;
+        for y in 0..height {
+            let v = (y as f64 + 0.5) / height as f64;
+            let sin_theta = (std::f64::consts::PI * v).sin();
+            for x in 0..width {
+                let u = (x as f64 + 0.5) / width as f64;
+                average = average + background.radiance(background.direction(u, v), spread) * sin_theta;
+                total_weight += sin_theta;
+            }
         }
-        None
+        let average = average * (1.0 / total_weight);
+
+        Self { background, distribution, average, samples: DEFAULT_ENVIRONMENT_SAMPLES }
+    }
+
+    /// Sets the number of samples shading takes from the environment, more give smoother shadows.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Converts a density over the map into one over solid angle, `v` is the row in the map.
+    fn solid_angle_pdf(pdf: f64, v: f64) -> f64 {
+        let sin_theta = (std::f64::consts::PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0
+        }
+        pdf / (2.0 * std::f64::consts::PI * std::f64::consts::PI * sin_theta)
+    }
+}
+
+impl Light for EnvironmentLight {
+    /// Treated as a single light the environment shines straight onto the surface.
+    fn direction(&self, _hit: Vec3, normal: Vec3) -> Vec3 {
+        normal
     }
 
-    fn shadow_hit(&self, _ray: &crate::math::Ray, _light_dist: f64) -> bool {
+    fn dist_to(&self, _point: Vec3) -> f64 {
+        f64::INFINITY
+    }
+
+    /// The mean radiance scaled so that a diffuse surface receives the irradiance of a uniform environment.
+    fn color(&self, _position: Vec3, _normal: Vec3) -> Color {
+        self.average * std::f64::consts::PI
+    }
+
+    fn sample(&self, _hit: Vec3, _normal: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        let ((u, v), pdf) = self.distribution.sample(u);
+        let pdf = Self::solid_angle_pdf(pdf, v);
+        if pdf <= 0.0 {
+            return None
+        }
+        let direction = self.background.direction(u, v);
+        Some(LightSample { direction, distance: f64::INFINITY, radiance: self.background.radiance(direction, 0.0), pdf })
+    }
+
+    fn pdf(&self, _origin: Vec3, direction: Vec3) -> f64 {
+        let (u, v) = self.background.uv(direction);
+        Self::solid_angle_pdf(self.distribution.pdf((u, v)), v)
+    }
+
+    /// Only rays that reach the background see the environment, anything else hit blocks it.
+    fn emission_pdf(&self, ray: &Ray, hit: &Intersection) -> f64 {
+        if hit.t.is_infinite() { self.pdf(ray.origin, ray.direction) } else { 0.0 }
+    }
+
+    fn is_delta(&self) -> bool {
         false
     }
-}
\ No newline at end of file
+
+    fn sample_count(&self) -> usize {
+        self.samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::uniform_sphere;
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() < tolerance, "{} != {}", a, b);
+    }
+
+    fn assert_same_direction(a: Vec3, b: Vec3) {
+        assert!((a - b).mag() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn equirectangular_round_trip() {
+        // Straight ahead along -z is the center of the map, +x a quarter turn to the right and +y the top row.
+        assert_same_direction(equirectangular_direction(0.5, 0.5), Vec3::new(0.0, 0.0, -1.0));
+        assert_same_direction(equirectangular_direction(0.75, 0.5), Vec3::new(1.0, 0.0, 0.0));
+        assert_close(equirectangular_uv(Vec3::new(0.0, 1.0, 0.0)).1, 0.0, 1e-9);
+
+        let rotated = BackgroundSurface::from_environment(SolidBackground::new(0.into())).with_rotation(0.7);
+        let unrotated = BackgroundSurface::from_environment(SolidBackground::new(0.into()));
+        for background in [&rotated.background, &unrotated.background] {
+            for j in 1..16 {
+                for i in 0..32 {
+                    let (u, v) = ((i as f64 + 0.25) / 32.0, j as f64 / 16.0);
+                    let (u2, v2) = equirectangular_uv(equirectangular_direction(u, v));
+                    assert_close(u2, u, 1e-9);
+                    assert_close(v2, v, 1e-9);
+
+                    let direction = background.direction(u, v);
+                    assert_close(direction.mag(), 1.0, 1e-9);
+                    let (u2, v2) = background.uv(direction);
+                    assert_close(u2, u, 1e-9);
+                    assert_close(v2, v, 1e-9);
+                    assert_same_direction(background.direction(u2, v2), direction);
+                }
+            }
+        }
+
+        // Rotating turns the map counter-clockwise seen from above, what was ahead is now to the left.
+        let angle = std::f64::consts::FRAC_PI_2;
+        let rotated = BackgroundSurface::from_environment(SolidBackground::new(0.into())).with_rotation(angle);
+        assert_same_direction(rotated.background.direction(0.5, 0.5), Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn environment_light_pdf() {
+        // A dim sky with a bright spot, rotated so the rotation has to be undone on both sides.
+        let background = BackgroundSurface::from_fn(64, 32, |direction| {
+            let spot = (direction * Vec3::new(0.6, 0.48, -0.64)).max(0.0).powi(32);
+            Color::new(0.2 + 50.0 * spot, 0.3, 0.4)
+        }).with_rotation(1.1);
+        let light = background.light();
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+
+        let n = 64;
+        for j in 0..n {
+            for i in 0..n {
+                let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let sample = light.sample(origin, normal, u).expect("sample");
+                let pdf = light.pdf(origin, sample.direction);
+                assert!((pdf - sample.pdf).abs() <= 1e-6 * sample.pdf, "sample pdf {} != pdf {}", sample.pdf, pdf);
+            }
+        }
+
+        // Averaged over the sphere the density times its area is one.
+        let n = 256;
+        let mut sum = 0.0;
+        for j in 0..n {
+            for i in 0..n {
+                let direction = uniform_sphere((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                sum += light.pdf(origin, direction);
+            }
+        }
+        let integral = sum / (n * n) as f64 * 4.0 * std::f64::consts::PI;
+        assert_close(integral, 1.0, 0.01);
+    }
+
+    /// Axis directions in the cube face order +x, -x, +y, -y, +z, -z.
+    fn axes() -> [Vec3; 6] {
+        [
+            Vec3::new(1.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0),
+        ]
+    }
+
+    /// Cross of `columns` x `rows` cells of 8x8 texels, the red channel tells the column and green the row of a
+    /// cell. Blue is 1 in the top half of a cell and 0 in the bottom half.
+    fn cross(columns: u32, rows: u32) -> DynamicImage {
+        image::Rgb32FImage::from_fn(columns * 8, rows * 8, |x, y| {
+            image::Rgb([(x / 8) as f32, (y / 8) as f32, if y % 8 < 4 { 1.0 } else { 0.0 }])
+        }).into()
+    }
+
+    #[test]
+    fn cube_faces() {
+        for (face, axis) in axes().into_iter().enumerate() {
+            let (index, s, t) = cube_face(axis);
+            assert_eq!(index, face, "{:?}", axis);
+            assert_close(s, 0.0, 1e-9);
+            assert_close(t, 0.0, 1e-9);
+        }
+        // Seen from the center with +y up, so up on every side face is towards the top of it.
+        for axis in [0, 1, 4, 5] {
+            let (_, _, t) = cube_face(axes()[axis] + Vec3::new(0.0, 0.5, 0.0));
+            assert!(t < 0.0, "face {}", axis);
+        }
+
+        // Horizontal cross: -x, -z, +x, +z in the middle row, +y above and -y below -z.
+        let horizontal = [(2, 1), (0, 1), (1, 0), (1, 2), (3, 1), (1, 1)];
+        // Vertical cross: the same but with +z below -y.
+        let vertical = [(2, 1), (0, 1), (1, 0), (1, 2), (1, 3), (1, 1)];
+        for (image, cells) in [(cross(4, 3), horizontal), (cross(3, 4), vertical)] {
+            let background = BackgroundSurface::from_image_with(image, Projection::Cubemap).unwrap();
+            for (axis, (column, row)) in axes().into_iter().zip(cells) {
+                let color = background.background.radiance(axis, 0.0);
+                assert_close(color.r, column as f64, 1e-6);
+                assert_close(color.g, row as f64, 1e-6);
+            }
+        }
+
+        // Looking a little up from +z sees the top half of its cell, which is the bottom half in the vertical
+        // cross where the face is upside down.
+        let up = Vec3::new(0.0, 0.4, 1.0);
+        let down = Vec3::new(0.0, -0.4, 1.0);
+        let horizontal = BackgroundSurface::from_image_with(cross(4, 3), Projection::Cubemap).unwrap();
+        assert_close(horizontal.background.radiance(up, 0.0).b, 1.0, 1e-6);
+        assert_close(horizontal.background.radiance(down, 0.0).b, 0.0, 1e-6);
+        let vertical = BackgroundSurface::from_image_with(cross(3, 4), Projection::Cubemap).unwrap();
+        assert_close(vertical.background.radiance(up, 0.0).b, 0.0, 1e-6);
+        assert_close(vertical.background.radiance(down, 0.0).b, 1.0, 1e-6);
+
+        assert!(BackgroundSurface::from_image_with(cross(4, 4), Projection::Cubemap).is_err());
+    }
+
+    #[test]
+    fn disk_projections() {
+        let assert_position = |projection: Projection, direction: Vec3, (s, t): (f64, f64)| {
+            let position = projection.disk_position(direction);
+            assert!((position.0 - s).abs() < 1e-9 && (position.1 - t).abs() < 1e-9, "{:?} {:?}: {:?}", projection, direction, position);
+        };
+        let [px, nx, py, ny, pz, nz] = axes();
+        let half = 0.5f64.sqrt();
+
+        // Angular: -z in the center, +z all around the rim and the sideways directions half way out.
+        assert_position(Projection::Angular, nz, (0.0, 0.0));
+        assert_position(Projection::Angular, px, (0.5, 0.0));
+        assert_position(Projection::Angular, nx, (-0.5, 0.0));
+        assert_position(Projection::Angular, py, (0.0, -0.5));
+        assert_position(Projection::Angular, ny, (0.0, 0.5));
+        let (s, t) = Projection::Angular.disk_position(pz);
+        assert_close((s * s + t * t).sqrt(), 1.0, 1e-9);
+
+        // Mirror ball: +z, back towards the camera, in the center, -z on the rim and the sideways directions where
+        // the normal of the ball is at 45 degrees.
+        assert_position(Projection::MirrorBall, pz, (0.0, 0.0));
+        assert_position(Projection::MirrorBall, px, (half, 0.0));
+        assert_position(Projection::MirrorBall, nx, (-half, 0.0));
+        assert_position(Projection::MirrorBall, py, (0.0, -half));
+        assert_position(Projection::MirrorBall, ny, (0.0, half));
+        let (s, t) = Projection::MirrorBall.disk_position(nz);
+        assert_close((s * s + t * t).sqrt(), 1.0, 1e-9);
+    }
+
+    /// Map of `width` x `height` texels whose red channel is the index of the texel.
+    fn numbered(width: u32, height: u32) -> MipLevel {
+        MipLevel { texels: (0..width * height).map(|i| [i as f32, 1.0, 0.0]).collect(), width, height }
+    }
+
+    #[test]
+    fn lookups_wrap_at_the_seam() {
+        let level = numbered(8, 4);
+        let filters = [EnvironmentFilter::Nearest, EnvironmentFilter::Bilinear, EnvironmentFilter::Bicubic];
+        for filter in filters {
+            for v in [0.0, 0.3, 1.0 - 1e-12, 1.0] {
+                let seam = level.lookup(0.0, v, filter);
+                assert_eq!(level.lookup(1.0, v, filter).r, seam.r, "{:?} at v = {}", filter, v);
+                // Just before the seam the interpolating filters already blend in the first column.
+                let before = level.lookup(1.0 - 1e-12, v, filter);
+                if filter == EnvironmentFilter::Nearest {
+                    assert_eq!(before.r, level.lookup(7.5 / 8.0, v, filter).r, "at v = {}", v);
+                } else {
+                    assert_close(before.r, seam.r, 1e-6);
+                }
+            }
+        }
+        // The columns on both sides of the seam are averaged there, the last rows are clamped.
+        assert_close(level.lookup(0.0, 1.0, EnvironmentFilter::Bilinear).r, (24.0 + 31.0) / 2.0, 1e-6);
+        assert_close(level.lookup(1.0, 0.0, EnvironmentFilter::Nearest).r, 0.0, 1e-6);
+    }
+
+    #[test]
+    fn bilinear_at_texel_centers() {
+        let level = numbered(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                let (u, v) = ((x as f64 + 0.5) / 8.0, (y as f64 + 0.5) / 4.0);
+                assert_eq!(level.lookup(u, v, EnvironmentFilter::Bilinear).r, (y * 8 + x) as f64);
+                assert_eq!(level.lookup(u, v, EnvironmentFilter::Bicubic).r, (y * 8 + x) as f64);
+            }
+        }
+    }
+
+    #[test]
+    fn catmull_rom_weights() {
+        for i in 0..=10 {
+            let weights = catmull_rom(i as f64 / 10.0);
+            assert_close(weights.iter().sum(), 1.0, 1e-12);
+        }
+        assert_eq!(catmull_rom(0.0), [0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn downsample_odd_sizes() {
+        // The odd last column and row fold into the blocks before them.
+        let level = numbered(5, 3).downsample();
+        assert_eq!((level.width, level.height), (2, 1));
+        let mean = |indices: &[u32]| indices.iter().sum::<u32>() as f32 / indices.len() as f32;
+        assert_eq!(level.texels[0][0], mean(&[0, 1, 5, 6, 10, 11]));
+        assert_eq!(level.texels[1][0], mean(&[2, 3, 4, 7, 8, 9, 12, 13, 14]));
+        assert_eq!(level.texels[1][1], 1.0);
+
+        let map = EnvironmentMap::new(numbered(7, 5));
+        let sizes: Vec<_> = map.levels.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, vec![(7, 5), (3, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn spread_picks_the_mip_level() {
+        let map = EnvironmentMap::new(numbered(16, 8));
+        let coarsest = map.levels.last().unwrap();
+        assert_eq!(coarsest.height, 1);
+        let (u, v) = (0.3, 0.6);
+        // Thin rays see the full map, a wide cone the single row that averages it all.
+        assert_eq!(map.lookup(u, v, 0.0).r, map.levels[0].lookup(u, v, map.filter).r);
+        assert_eq!(map.lookup(u, v, std::f64::consts::PI).r, coarsest.lookup(u, v, map.filter).r);
+        // A texel of the second level spans two rows of the full map.
+        let spread = 2.0 * std::f64::consts::PI / 8.0;
+        assert_close(map.lookup(u, v, spread).r, map.levels[1].lookup(u, v, map.filter).r, 1e-9);
+    }
+
+    fn uniform(pixel: [f32; 3]) -> DynamicImage {
+        image::Rgb32FImage::from_pixel(8, 4, image::Rgb(pixel)).into()
+    }
+
+    fn assert_color(color: Color, expected: [f64; 3], tolerance: f64) {
+        for (c, e) in [color.r, color.g, color.b].into_iter().zip(expected) {
+            assert!((c - e).abs() <= tolerance * e, "{:?} != {:?}", color, expected);
+        }
+    }
+
+    #[test]
+    fn float_maps_keep_high_dynamic_range() {
+        let radiance = [8.0, 0.5, 2.0];
+        let pixels = vec![image::Rgb(radiance); 8 * 4];
+        let hdr = std::env::temp_dir().join(format!("raytracer_{}_float_map.hdr", std::process::id()));
+        image::codecs::hdr::HdrEncoder::new(File::create(&hdr).unwrap()).encode(&pixels, 8, 4).unwrap();
+        let exr = hdr.with_extension("exr");
+        uniform(radiance).save(&exr).unwrap();
+
+        for path in [hdr, exr] {
+            let background = BackgroundSurface::open(path.to_str().unwrap()).unwrap().background;
+            for direction in axes() {
+                // Radiance HDR keeps an 8 bit mantissa per channel.
+                assert_color(background.radiance(direction, 0.0), radiance.map(f64::from), 1.0 / 128.0);
+            }
+        }
+    }
+
+    #[test]
+    fn only_integer_images_are_srgb_decoded() {
+        let direction = Vec3::new(0.0, 0.0, -1.0);
+        let float = BackgroundSurface::from_image(uniform([0.5, 0.25, 1.0])).background;
+        assert_color(float.radiance(direction, 0.0), [0.5, 0.25, 1.0], 1e-6);
+
+        let integer = image::RgbImage::from_pixel(8, 4, image::Rgb([128, 64, 255])).into();
+        let integer = BackgroundSurface::from_image(integer).background;
+        let expected = [128.0, 64.0, 255.0].map(|c: f64| srgb_to_linear(c / 255.0));
+        assert_color(integer.radiance(direction, 0.0), expected, 1e-6);
+        assert!(expected[0] < 0.25);
+    }
+
+    #[test]
+    fn intensity_scales_background_and_light() {
+        let background = BackgroundSurface::from_image(uniform([0.5, 0.25, 1.0])).with_intensity(3.0);
+        let expected = [1.5, 0.75, 3.0];
+        let (origin, normal) = (Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        for direction in axes() {
+            assert_color(background.background.radiance(direction, 0.0), expected, 1e-6);
+        }
+        let light = background.light();
+        let sample = light.sample(origin, normal, (0.3, 0.7)).expect("sample");
+        assert_color(sample.radiance, expected, 1e-6);
+        assert_color(light.color(origin, normal), expected.map(|c| c * std::f64::consts::PI), 1e-6);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::math::{Aabb, Ray, Vec3};
+
+const BIN_COUNT: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+
+/// Bounding volume hierarchy over a list of bounding boxes, built with a binned surface area heuristic.
+/// The hierarchy only stores indices into the list it was built from, the caller performs the actual
+/// primitive intersections in the callbacks passed to [`Bvh::closest_hit`] and [`Bvh::any_hit`].
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    /// Index of the first child for inner nodes, index into `indices` for leaves.
+    start: usize,
+    /// Number of primitives, zero marks an inner node whose children are `start` and `start + 1`.
+    count: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Primitive {
+    bounds: Aabb,
+    centroid: Vec3,
+    index: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+impl Bvh {
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut primitives: Vec<Primitive> = bounds.iter()
+            .enumerate()
+            .map(|(index, bounds)| Primitive { bounds: *bounds, centroid: bounds.centroid(), index })
+            .collect();
+
+        let mut bvh = Self { nodes: Vec::with_capacity(2 * primitives.len()), indices: Vec::with_capacity(primitives.len()) };
+        if !primitives.is_empty() {
+            bvh.nodes.push(Node { bounds: Aabb::empty(), start: 0, count: 0 });
+            bvh.build_node(0, &mut primitives);
+        }
+        bvh
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bounds)
+    }
+
+    fn build_node(&mut self, node: usize, primitives: &mut [Primitive]) {
+        let bounds = primitives.iter().fold(Aabb::empty(), |acc, p| acc.union(p.bounds));
+        self.nodes[node].bounds = bounds;
+
+        if primitives.len() > MAX_LEAF_SIZE {
+            if let Some((axis, split)) = Self::find_split(&bounds, primitives) {
+                let centroid_bounds = primitives.iter().fold(Aabb::empty(), |acc, p| acc.grow(p.centroid));
+                let mid = partition(primitives, |p| bin_index(&centroid_bounds, axis, p.centroid) < split);
+
+                if mid > 0 && mid < primitives.len() {
+                    let left = self.nodes.len();
+                    self.nodes.push(Node { bounds: Aabb::empty(), start: 0, count: 0 });
+                    self.nodes.push(Node { bounds: Aabb::empty(), start: 0, count: 0 });
+                    self.nodes[node].start = left;
+
+                    let (left_primitives, right_primitives) = primitives.split_at_mut(mid);
+                    self.build_node(left, left_primitives);
+                    self.build_node(left + 1, right_primitives);
+                    return
+                }
+            }
+        }
+
+        self.nodes[node].start = self.indices.len();
+        self.nodes[node].count = primitives.len();
+        self.indices.extend(primitives.iter().map(|p| p.index));
+    }
+
+    /// Finds the cheapest bin boundary over all three axes, or `None` if a leaf is cheaper than any split.
+    fn find_split(bounds: &Aabb, primitives: &[Primitive]) -> Option<(usize, usize)> {
+        let centroid_bounds = primitives.iter().fold(Aabb::empty(), |acc, p| acc.grow(p.centroid));
+        let leaf_cost = INTERSECTION_COST * primitives.len() as f64;
+        let inv_area = 1.0 / bounds.surface_area().max(f64::MIN_POSITIVE);
+        let mut best: Option<(usize, usize, f64)> = None;
+
+        for axis in 0..3 {
+            if centroid_bounds.extent()[axis] <= 0.0 {
+                continue
+            }
+
+            let mut bins = [Bin { bounds: Aabb::empty(), count: 0 }; BIN_COUNT];
+            for primitive in primitives {
+                let bin = &mut bins[bin_index(&centroid_bounds, axis, primitive.centroid)];
+                bin.bounds = bin.bounds.union(primitive.bounds);
+                bin.count += 1;
+            }
+
+            // Sweep from the right to collect the cost of every right-hand side, then from the left.
+            let mut right_area = [0.0; BIN_COUNT];
+            let mut right_count = [0; BIN_COUNT];
+            let mut acc = Bin { bounds: Aabb::empty(), count: 0 };
+            for split in (1..BIN_COUNT).rev() {
+                acc.bounds = acc.bounds.union(bins[split].bounds);
+                acc.count += bins[split].count;
+                right_area[split] = acc.bounds.surface_area();
+                right_count[split] = acc.count;
+            }
+
+            let mut acc = Bin { bounds: Aabb::empty(), count: 0 };
+            for split in 1..BIN_COUNT {
+                acc.bounds = acc.bounds.union(bins[split - 1].bounds);
+                acc.count += bins[split - 1].count;
+                if acc.count == 0 || right_count[split] == 0 {
+                    continue
+                }
+
+                let cost = TRAVERSAL_COST + INTERSECTION_COST * inv_area * (
+                    acc.bounds.surface_area() * acc.count as f64 + right_area[split] * right_count[split] as f64
+                );
+                if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+
+        match best {
+            Some((axis, split, cost)) if cost < leaf_cost => Some((axis, split)),
+            _ => None,
+        }
+    }
+
+    /// Visits the primitives front to back. `hit` receives a primitive index and the current closest distance
+    /// and returns the distance of a closer hit. Returns the index and distance of the closest hit.
+    pub fn closest_hit<F>(&self, ray: &Ray, max_distance: f64, mut hit: F) -> Option<(usize, f64)>
+    where
+        F: FnMut(usize, f64) -> Option<f64>,
+    {
+        let inv_direction = inverse(ray.direction);
+        let mut closest: Option<(usize, f64)> = None;
+        let mut min_distance = max_distance;
+
+        let mut stack = Vec::with_capacity(64);
+        if let Some(entry) = self.nodes.first().and_then(|root| root.bounds.intersect(ray, inv_direction, min_distance)) {
+            stack.push((0, entry));
+        }
+
+        while let Some((index, entry)) = stack.pop() {
+            if entry > min_distance {
+                continue
+            }
+
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                for &primitive in &self.indices[node.start..node.start + node.count] {
+                    if let Some(distance) = hit(primitive, min_distance) {
+                        if distance <= min_distance {
+                            min_distance = distance;
+                            closest = Some((primitive, distance));
+                        }
+                    }
+                }
+                continue
+            }
+
+            let left = self.nodes[node.start].bounds.intersect(ray, inv_direction, min_distance);
+            let right = self.nodes[node.start + 1].bounds.intersect(ray, inv_direction, min_distance);
+            // Push the farther child first so the nearer one is visited first and shrinks `min_distance`.
+            match (left, right) {
+                (Some(l), Some(r)) if l <= r => stack.extend([(node.start + 1, r), (node.start, l)]),
+                (Some(l), Some(r)) => stack.extend([(node.start, l), (node.start + 1, r)]),
+                (Some(l), None) => stack.push((node.start, l)),
+                (None, Some(r)) => stack.push((node.start + 1, r)),
+                (None, None) => {},
+            }
+        }
+
+        closest
+    }
+
+    /// Returns true as soon as `hit` reports an intersection for any primitive whose box the ray enters
+    /// before `max_distance`.
+    pub fn any_hit<F>(&self, ray: &Ray, max_distance: f64, mut hit: F) -> bool
+    where
+        F: FnMut(usize) -> bool,
+    {
+        let inv_direction = inverse(ray.direction);
+
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bounds.intersect(ray, inv_direction, max_distance).is_none() {
+                continue
+            }
+
+            if node.count > 0 {
+                if self.indices[node.start..node.start + node.count].iter().any(|&primitive| hit(primitive)) {
+                    return true
+                }
+            } else {
+                stack.extend([node.start + 1, node.start]);
+            }
+        }
+
+        false
+    }
+}
+
+fn inverse(direction: Vec3) -> Vec3 {
+    Vec3::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z)
+}
+
+fn bin_index(centroid_bounds: &Aabb, axis: usize, centroid: Vec3) -> usize {
+    let extent = centroid_bounds.extent()[axis];
+    if extent <= 0.0 {
+        return 0
+    }
+    let bin = ((centroid[axis] - centroid_bounds.min[axis]) / extent * BIN_COUNT as f64) as usize;
+    bin.min(BIN_COUNT - 1)
+}
+
+/// Moves all elements matching `predicate` to the front and returns how many there are.
+fn partition<T, P: Fn(&T) -> bool>(items: &mut [T], predicate: P) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{math::{Ray, Vec3}, surface::Scene, meshes::{Sphere, Triangle}, materials::AlbedoMaterial, sampling::Rng};
+
+    fn random_point(rng: &mut Rng, extent: f64) -> Vec3 {
+        Vec3::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5, rng.next_f64() - 0.5) * (2.0 * extent)
+    }
+
+    #[test]
+    fn traversal_matches_linear_scan() {
+        let mut rng = Rng::new(7);
+        let mut scene = Scene::new();
+        for i in 0..300 {
+            let material = Box::new(AlbedoMaterial::new(0.into()));
+            let center = random_point(&mut rng, 10.0);
+            if i % 2 == 0 {
+                scene.push(Box::new(Sphere::new(material, center, 0.1 + rng.next_f64())));
+            } else {
+                let [b, c] = [random_point(&mut rng, 1.5), random_point(&mut rng, 1.5)];
+                scene.push(Box::new(Triangle::new(material, center, center + b, center + c)));
+            }
+        }
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = random_point(&mut rng, 15.0);
+            let ray = Ray::new(origin, (random_point(&mut rng, 1.0) - origin * 0.05).normalize());
+
+            let linear = scene.surfaces().iter().enumerate()
+                .filter_map(|(index, surface)| surface.intersect(&ray, f64::INFINITY).map(|hit| (index, hit.t)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let traversed = scene.intersect(&ray, f64::INFINITY).map(|hit| (hit.surface, hit.t));
+            assert_eq!(traversed, linear, "closest hit differs for {:?}", ray);
+
+            for max_distance in [1.0, 5.0, 20.0] {
+                let linear = scene.surfaces().iter().any(|surface| surface.shadow_hit(&ray, max_distance));
+                assert_eq!(scene.occluded(&ray, max_distance), linear, "occlusion within {} differs for {:?}", max_distance, ray);
+            }
+            hits += linear.is_some() as usize;
+        }
+        assert!(hits > 200, "only {} of the rays hit anything", hits);
+    }
+}
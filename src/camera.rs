@@ -1,12 +1,12 @@
+use std::{path::Path, sync::{atomic::{AtomicUsize, Ordering}, mpsc}, thread};
+
 use image::{ImageFormat, ImageError};
 
-use crate::{math::{Vec3, Ray}, surface::{Scene}, light::SceneLights, color::Color};
+use crate::{math::{Vec3, Ray, RayKind}, surface::{Scene}, light::SceneLights, color::Color, sampling::{Rng, SamplePattern, Filter}, tonemap::ExportSettings, output::OutputFormat, integrator::{self, Integrator}};
 
 pub struct Camera {
     eye: Vec3,
-    look_at: Vec3,
     screen_dist: f64,
-    up: Vec3,
 
     u: Vec3,
     v: Vec3,
@@ -21,17 +21,22 @@ impl Camera {
 
         Self {
             eye,
-            look_at,
             screen_dist,
-            up: up.normalize(),
             u, v, w
         }
     }
 
     fn pixel_to_ray(&self, x: usize, y: usize, screen: &Screen) -> Ray {
-        let factor_u = (x as f64 + 0.5) * (screen.real.0 / (screen.width as f64)) - 0.5 * screen.real.0;
-        let factor_v = 0.5 * screen.real.1 - (y as f64 + 0.5) * (screen.real.1 / (screen.height as f64)); 
+        self.film_to_ray(x as f64 + 0.5, y as f64 + 0.5, screen)
+    }
 
+    /// Ray through a point on the screen given in pixel units, `(0, 0)` is the top left corner of the first pixel.
+    fn film_to_ray(&self, x: f64, y: f64, screen: &Screen) -> Ray {
+        let factor_u = x * (screen.real.0 / (screen.width as f64)) - 0.5 * screen.real.0;
+        let factor_v = 0.5 * screen.real.1 - y * (screen.real.1 / (screen.height as f64)); 
+
+        // Rays near the center of the screen cover about a pixel's width at the screen distance.
+        let spread = screen.real.0 / (screen.width as f64 * self.screen_dist);
         Ray::new(
             self.eye,
             Vec3::linear_combine(
@@ -39,47 +44,271 @@ impl Camera {
                 factor_v, &self.v, 
                 -self.screen_dist, &self.w
             )
-        )
+        ).with_spread(spread).with_kind(RayKind::Camera)
     }
 
-    pub fn trace(&self, ray: Ray, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> [u8; 3] {
+    /// Linear radiance along the ray, black if it doesn't hit anything.
+    pub fn trace(&self, ray: Ray, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Color {
         if let Some(color) = self.trace_ray(&ray, scene, lights, bounce_count) {
-            color.into()
+            color
         } else {
-            [0,0,0]
+            0.into()
         }
     }
 
     fn trace_ray(&self, ray: &Ray, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Option<Color> {
-        let mut min_distance = f64::INFINITY;
-        let mut result: Option<Color> = None;
+        scene.hit(ray, lights, bounce_count).map(|info| info.color())
+    }
 
-        for surface in scene {
-            if let Some(info) = surface.hit(&ray, scene, lights, bounce_count, min_distance) {
-                min_distance = (info.position - self.eye).mag();
-                result = Some(info.color());
-            }
+    /// Radiance along a camera ray and its alpha, zero where the ray misses the scene or hits a holdout.
+    fn radiance(&self, ray: Ray, scene: &Scene, lights: Option<&SceneLights>, settings: &RenderSettings, rng: &mut Rng) -> (Color, f64) {
+        match settings.integrator {
+            Integrator::Whitted => {
+                let Some(hit) = scene.intersect(&ray, f64::INFINITY) else {
+                    return (0.into(), 0.0)
+                };
+                let material = scene.surfaces()[hit.surface].get_material();
+                if material.is_holdout() {
+                    return (0.into(), 0.0)
+                }
+                let info = material.calc_mat(&ray, hit.position, hit.normal, scene, lights, settings.max_bounces);
+                (info.map_or(0.into(), |info| info.color()), 1.0)
+            },
+            Integrator::PathTracer => integrator::trace_path(ray, scene, lights, settings.max_bounces, rng),
         }
+    }
 
-        result
+    fn render_pixel(&self, x: usize, y: usize, scene: &Scene, lights: Option<&SceneLights>, screen: &Screen, settings: &RenderSettings) -> Pixel {
+        let mut pixel = Pixel::default();
+        let mut rng = Rng::for_pixel(x, y);
+
+        if settings.samples <= 1 {
+            let ray = self.pixel_to_ray(x, y, screen);
+            let (color, alpha) = self.radiance(ray, scene, lights, settings, &mut rng);
+            pixel.add_sample(color, alpha, 1.0);
+            return pixel
+        }
+
+        // Distribute the samples around the pixel center proportional to the filter.
+        let mut samples = Vec::with_capacity(settings.samples);
+        for (u, v) in settings.pattern.generate(settings.samples, &mut rng) {
+            let (dx, dy, weight) = settings.filter.sample(u, v);
+            let ray = self.film_to_ray(x as f64 + 0.5 + dx, y as f64 + 0.5 + dy, screen);
+            let (color, alpha) = self.radiance(ray, scene, lights, settings, &mut rng);
+            samples.push((color, alpha, weight));
+        }
+
+        // Filters with negative lobes can cancel out with very few samples, fall back to a plain average then.
+        let weight_sum: f64 = samples.iter().map(|(_, _, weight)| weight).sum();
+        let uniform = weight_sum.abs() < 1e-6;
+        for (color, alpha, weight) in samples {
+            pixel.add_sample(color, alpha, if uniform { 1.0 } else { weight });
+        }
+        pixel
     }
 
-    pub fn render_scene(&self, scene: &Scene, lights: Option<&SceneLights>, mut screen: Screen) -> Screen {
-        for (x,y) in screen.clone().into_iter() {
-            let ray = self.pixel_to_ray(x, y, &screen);
-            screen.pixels[x][y] = self.trace(ray, &scene, lights, 10);
+    fn render_tile(&self, tile: &Tile, scene: &Scene, lights: Option<&SceneLights>, screen: &Screen, settings: &RenderSettings) -> Vec<Pixel> {
+        tile.pixels()
+            .map(|(x, y)| self.render_pixel(x, y, scene, lights, screen, settings))
+            .collect()
+    }
+
+    pub fn render_scene(&self, scene: &Scene, lights: Option<&SceneLights>, screen: Screen) -> Screen {
+        self.render_scene_with(scene, lights, screen, &RenderSettings::default())
+    }
+
+    pub fn render_scene_with(&self, scene: &Scene, lights: Option<&SceneLights>, screen: Screen, settings: &RenderSettings) -> Screen {
+        self.render_scene_with_progress(scene, lights, screen, settings, |_, _| {})
+    }
+
+    /// Renders the scene with the given settings. The screen is split into tiles that worker threads pull from a
+    /// shared queue, with a single thread the tiles are rendered in order on the calling thread. Every pixel is
+    /// traced independently, so the result doesn't depend on the thread count.
+    ///
+    /// `progress` is called on the calling thread with the number of finished and total tiles.
+    pub fn render_scene_with_progress<F>(&self, scene: &Scene, lights: Option<&SceneLights>, mut screen: Screen, settings: &RenderSettings, mut progress: F) -> Screen
+    where
+        F: FnMut(usize, usize),
+    {
+        let tiles = screen.tiles(settings.tile_size);
+        let mut rendered = Vec::with_capacity(tiles.len());
+
+        if settings.threads <= 1 {
+            for tile in &tiles {
+                rendered.push((*tile, self.render_tile(tile, scene, lights, &screen, settings)));
+                progress(rendered.len(), tiles.len());
+            }
+        } else {
+            let next_tile = AtomicUsize::new(0);
+            let (sender, receiver) = mpsc::channel();
+
+            thread::scope(|scope| {
+                for _ in 0..settings.threads.min(tiles.len()) {
+                    let sender = sender.clone();
+                    let (tiles, next_tile, screen) = (&tiles, &next_tile, &screen);
+                    scope.spawn(move || {
+                        while let Some(tile) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                            let pixels = self.render_tile(tile, scene, lights, screen, settings);
+                            if sender.send((*tile, pixels)).is_err() {
+                                break
+                            }
+                        }
+                    });
+                }
+                drop(sender);
+                for result in receiver {
+                    rendered.push(result);
+                    progress(rendered.len(), tiles.len());
+                }
+            });
+        }
+
+        for (tile, pixels) in rendered {
+            for ((x, y), pixel) in tile.pixels().zip(pixels) {
+                screen.pixels[x][y] = pixel;
+            }
         }
         screen
     }
     
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    pub threads: usize,
+    pub tile_size: usize,
+    /// How often rays may be reflected before the recursion stops, the maximum path length for path tracing.
+    pub max_bounces: usize,
+    /// Samples per pixel.
+    pub samples: usize,
+    pub pattern: SamplePattern,
+    pub filter: Filter,
+    pub integrator: Integrator,
+}
+
+impl RenderSettings {
+    pub fn new() -> Self {
+        Self {
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            tile_size: 32,
+            max_bounces: 10,
+            samples: 1,
+            pattern: SamplePattern::Regular,
+            filter: Filter::Box,
+            integrator: Integrator::Whitted,
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    pub fn with_tile_size(mut self, tile_size: usize) -> Self {
+        self.tile_size = tile_size.max(1);
+        self
+    }
+
+    pub fn with_max_bounces(mut self, max_bounces: usize) -> Self {
+        self.max_bounces = max_bounces;
+        self
+    }
+
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn with_pattern(mut self, pattern: SamplePattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_integrator(mut self, integrator: Integrator) -> Self {
+        self.integrator = integrator;
+        self
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A rectangular block of pixels that is rendered as one unit of work.
+#[derive(Debug, Clone, Copy)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let tile = *self;
+        (tile.y..tile.y + tile.height).flat_map(move |y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+    }
+}
+
+/// Weighted sum of the linear radiance samples that make up a pixel.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pixel {
+    pub rgb: [f32; 3],
+    pub alpha: f32,
+    pub weight: f32,
+}
+
+impl Pixel {
+    pub fn add_sample(&mut self, color: Color, alpha: f64, weight: f64) {
+        self.rgb[0] += (color.r * weight) as f32;
+        self.rgb[1] += (color.g * weight) as f32;
+        self.rgb[2] += (color.b * weight) as f32;
+        self.alpha += (alpha * weight) as f32;
+        self.weight += weight as f32;
+    }
+
+    /// Merges the samples of another pixel, e.g. from a second render pass.
+    pub fn merge(&mut self, other: &Pixel) {
+        for (channel, other) in self.rgb.iter_mut().zip(other.rgb) {
+            *channel += other;
+        }
+        self.alpha += other.alpha;
+        self.weight += other.weight;
+    }
+
+    /// The reconstructed linear color, black if no samples were added. Transparent samples count as black, so the
+    /// color is premultiplied by the alpha.
+    pub fn color(&self) -> Color {
+        if self.weight == 0.0 {
+            return 0.into()
+        }
+        let inv_weight = 1.0 / self.weight as f64;
+        Color::new(self.rgb[0] as f64 * inv_weight, self.rgb[1] as f64 * inv_weight, self.rgb[2] as f64 * inv_weight)
+    }
+
+    /// Coverage of the pixel, the fraction of the samples that hit something other than a holdout.
+    pub fn alpha(&self) -> f64 {
+        if self.weight == 0.0 {
+            return 0.0
+        }
+        self.alpha as f64 / self.weight as f64
+    }
+}
+
+/// The film of the camera, stores linear floating point radiance that is only quantized on export.
 #[derive(Debug, Clone)]
 pub struct Screen {
     pub real: (f64, f64),
     pub width: usize,
     pub height: usize,
-    pub pixels: Vec<Vec<[u8; 3]>>,
+    pub pixels: Vec<Vec<Pixel>>,
 
     current: (usize, usize),
 }
@@ -89,23 +318,106 @@ impl Screen {
         Self { real: (real_width, real_height), width, height, pixels: Self::init_screen(width, height), current: (0, 0) }
     }
 
-    fn init_screen(width: usize, height: usize) -> Vec<Vec<[u8; 3]>> {
+    fn init_screen(width: usize, height: usize) -> Vec<Vec<Pixel>> {
         let mut res = Vec::with_capacity(width);
         for x in 0..width {
             res.push(Vec::with_capacity(height));
             for _ in 0..height {
-                res[x].push([0, 0, 0])
+                res[x].push(Pixel::default())
             }
         }
         res
     }
 
+    /// Linear color of a pixel.
+    pub fn color(&self, x: usize, y: usize) -> Color {
+        self.pixels[x][y].color()
+    }
+
+    /// Alpha of a pixel, clamped to `[0, 1]` against the negative lobes of some filters.
+    pub fn alpha(&self, x: usize, y: usize) -> f64 {
+        self.pixels[x][y].alpha().clamp(0.0, 1.0)
+    }
+
+    /// Color of a pixel divided by its alpha, what is left where the pixel is covered. Display transforms have to
+    /// be applied to this one and not to the premultiplied color.
+    pub fn straight_color(&self, x: usize, y: usize) -> Color {
+        let alpha = self.alpha(x, y);
+        if alpha <= 0.0 {
+            return 0.into()
+        }
+        self.color(x, y) * (1.0 / alpha)
+    }
+
+    pub fn tiles(&self, tile_size: usize) -> Vec<Tile> {
+        let tile_size = tile_size.max(1);
+        let mut tiles = Vec::new();
+        for y in (0..self.height).step_by(tile_size) {
+            for x in (0..self.width).step_by(tile_size) {
+                tiles.push(Tile {
+                    x, y,
+                    width: tile_size.min(self.width - x),
+                    height: tile_size.min(self.height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    /// Saves the image in the format matching the extension of `path`, PNG if there is none, with the default
+    /// [`ExportSettings`].
     pub fn export(&self, path: &str) -> Result<(), ImageError> {
+        self.export_with_format(path, OutputFormat::from_path(path).unwrap_or(OutputFormat::Ldr(ImageFormat::Png)))
+    }
+
+    pub fn export_with_format<F: Into<OutputFormat>>(&self, path: &str, format: F) -> Result<(), ImageError> {
+        self.export_with(path, format, &ExportSettings::default())
+    }
+
+    /// Saves the image, integer formats are written after applying the exposure, tone mapping and transfer
+    /// function of `settings`.
+    pub fn export_with<F: Into<OutputFormat>>(&self, path: &str, format: F, settings: &ExportSettings) -> Result<(), ImageError> {
+        format.into().save(self, Path::new(path), settings)
+    }
+
+    pub fn to_rgb8(&self, settings: &ExportSettings) -> image::RgbImage {
         let mut image = image::RgbImage::new(self.width as u32, self.height as u32);
-        for (x, y) in self.clone().into_iter() {
-            image.put_pixel(x as u32, y as u32, image::Rgb(self.pixels[x][y]))
+        for (x, y) in self.clone() {
+            image.put_pixel(x as u32, y as u32, image::Rgb(settings.to_bytes(self.color(x, y))))
         }
-        image.save_with_format(path, ImageFormat::Png)
+        image
+    }
+
+    /// 8 bit image with straight, not premultiplied, alpha as PNG expects it.
+    pub fn to_rgba8(&self, settings: &ExportSettings) -> image::RgbaImage {
+        let mut image = image::RgbaImage::new(self.width as u32, self.height as u32);
+        for (x, y) in self.clone() {
+            let [r, g, b] = settings.to_bytes(self.straight_color(x, y));
+            let alpha = (self.alpha(x, y) * 255.0).round() as u8;
+            image.put_pixel(x as u32, y as u32, image::Rgba([r, g, b, alpha]))
+        }
+        image
+    }
+
+    /// 16 bit image with straight alpha, see [`Screen::to_rgba8`].
+    pub fn to_rgba16(&self, settings: &ExportSettings) -> image::ImageBuffer<image::Rgba<u16>, Vec<u16>> {
+        let mut image = image::ImageBuffer::new(self.width as u32, self.height as u32);
+        for (x, y) in self.clone() {
+            let color = settings.to_display(self.straight_color(x, y));
+            let rgba = [color.r, color.g, color.b, self.alpha(x, y)].map(|c| (c * 65535.0).round().clamp(0.0, 65535.0) as u16);
+            image.put_pixel(x as u32, y as u32, image::Rgba(rgba))
+        }
+        image
+    }
+
+    pub fn to_rgb16(&self, settings: &ExportSettings) -> image::ImageBuffer<image::Rgb<u16>, Vec<u16>> {
+        let mut image = image::ImageBuffer::new(self.width as u32, self.height as u32);
+        for (x, y) in self.clone() {
+            let color = settings.to_display(self.color(x, y));
+            let rgb = [color.r, color.g, color.b].map(|c| (c * 65535.0).round().clamp(0.0, 65535.0) as u16);
+            image.put_pixel(x as u32, y as u32, image::Rgb(rgb))
+        }
+        image
     }
 }
 
@@ -113,15 +425,96 @@ impl Iterator for Screen {
     type Item = (usize, usize);
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.width == 0 || self.current.1 >= self.height {
+            return None;
+        }
+        let pixel = self.current;
+
         self.current.0 += 1;
         if self.current.0 >= self.width {
             self.current.0 = 0;
             self.current.1 += 1;
         }
-        if self.current.1 >= self.height {
-            return None;
+
+        Some(pixel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{light::PointLight, materials::{DiffuseMaterial, HoldoutMaterial, SDRMaterial}, meshes::{Plane, Sphere}};
+
+    fn render(threads: usize, integrator: Integrator) -> Screen {
+        let camera = Camera::new(Vec3::new(0.0, 2.0, 8.0), Vec3::new(0.0, 0.0, 0.0), 4.0, Vec3::new(0.0, 1.0, 0.0));
+        let mut scene = Scene::new();
+        scene.push(Box::new(Plane::new(Box::new(DiffuseMaterial::new(Color::new(0.8, 0.8, 0.8), 1.0)), Vec3::new(0.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0))));
+        scene.push(Box::new(Sphere::new(Box::new(SDRMaterial::new(Color::new(0.9, 0.2, 0.1), 1.0, 0.5, 20.0, 0.5)), Vec3::new(-1.0, 0.0, 0.0), 1.0)));
+        scene.push(Box::new(Sphere::new(Box::new(DiffuseMaterial::new(Color::new(0.1, 0.4, 0.9), 1.0)), Vec3::new(1.5, 0.0, -1.0), 1.0)));
+        let lights: SceneLights = vec![Box::new(PointLight::new(Vec3::new(2.0, 5.0, 3.0), Color::new(1.0, 1.0, 1.0), 1.0))];
+
+        // An odd size leaves partial tiles, and the wide filter spreads samples across tile borders.
+        let settings = RenderSettings::new()
+            .with_threads(threads)
+            .with_tile_size(8)
+            .with_samples(4)
+            .with_pattern(SamplePattern::Jittered)
+            .with_filter(Filter::MitchellNetravali)
+            .with_integrator(integrator);
+        camera.render_scene_with(&scene, Some(&lights), Screen::new(37, 23, 4.0, 2.5), &settings)
+    }
+
+    #[test]
+    fn tiles_match_serial_render() {
+        for integrator in [Integrator::Whitted, Integrator::PathTracer] {
+            let serial = render(1, integrator);
+            let tiled = render(4, integrator);
+            assert!(serial.pixels == tiled.pixels, "{:?} render differs between 1 and 4 threads", integrator);
+        }
+    }
+
+    #[test]
+    fn holdouts_and_misses_are_transparent() {
+        // A holdout sphere on the left and a plain one on the right, with nothing behind them.
+        let camera = Camera::new(Vec3::new(0.0, 0.0, 8.0), Vec3::new(0.0, 0.0, 0.0), 4.0, Vec3::new(0.0, 1.0, 0.0));
+        let mut scene = Scene::new();
+        let diffuse = || Box::new(DiffuseMaterial::new(Color::new(0.8, 0.8, 0.8), 1.0));
+        scene.push(Box::new(Sphere::new(Box::new(HoldoutMaterial::new(diffuse())), Vec3::new(-1.5, 0.0, 0.0), 1.0)));
+        scene.push(Box::new(Sphere::new(diffuse(), Vec3::new(1.5, 0.0, 0.0), 1.0)));
+        let lights: SceneLights = vec![Box::new(PointLight::new(Vec3::new(0.0, 0.0, 8.0), Color::new(1.0, 1.0, 1.0), 1.0))];
+
+        for integrator in [Integrator::Whitted, Integrator::PathTracer] {
+            let settings = RenderSettings::new().with_threads(1).with_integrator(integrator);
+            let screen = camera.render_scene_with(&scene, Some(&lights), Screen::new(16, 8, 4.0, 2.0), &settings);
+            let (holdout, visible, miss) = ((4, 4), (11, 4), (0, 0));
+            assert_eq!(screen.alpha(holdout.0, holdout.1), 0.0, "{:?}", integrator);
+            assert!(screen.color(holdout.0, holdout.1).is_black(), "{:?}", integrator);
+            assert_eq!(screen.alpha(miss.0, miss.1), 0.0, "{:?}", integrator);
+            assert!(screen.color(miss.0, miss.1).is_black(), "{:?}", integrator);
+            assert_eq!(screen.alpha(visible.0, visible.1), 1.0, "{:?}", integrator);
+            assert!(!screen.color(visible.0, visible.1).is_black(), "{:?}", integrator);
+
+            let image = screen.to_rgba8(&ExportSettings::default());
+            assert_eq!(image.get_pixel(holdout.0 as u32, holdout.1 as u32).0, [0, 0, 0, 0]);
+            assert_eq!(image.get_pixel(miss.0 as u32, miss.1 as u32).0[3], 0);
+            assert_eq!(image.get_pixel(visible.0 as u32, visible.1 as u32).0[3], 255);
         }
+    }
+
+    #[test]
+    fn rgba8_has_straight_alpha() {
+        // Half of the pixel is covered, its premultiplied color is half of the color of what covers it.
+        let mut screen = Screen::new(2, 1, 1.0, 1.0);
+        screen.pixels[0][0].add_sample(Color::new(0.8, 0.4, 0.2), 1.0, 1.0);
+        screen.pixels[0][0].add_sample(0.into(), 0.0, 1.0);
+        screen.pixels[1][0].add_sample(0.into(), 0.0, 1.0);
+        let settings = ExportSettings::default();
+        let color = screen.color(0, 0);
+        assert!((color.r - 0.4).abs() < 1e-6 && (color.g - 0.2).abs() < 1e-6, "{:?}", color);
 
-        Some((self.current.0, self.current.1))
+        let image = screen.to_rgba8(&settings);
+        let [r, g, b] = settings.to_bytes(Color::new(0.8, 0.4, 0.2));
+        assert_eq!(image.get_pixel(0, 0).0, [r, g, b, 128]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 0, 0]);
     }
-}
\ No newline at end of file
+}
@@ -13,6 +13,24 @@ impl Color {
             r,g,b
         }
     }
+
+    /// Applies `f` to every channel.
+    pub fn map<F: Fn(f64) -> f64>(self, f: F) -> Self {
+        Self::new(f(self.r), f(self.g), f(self.b))
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r <= 0.0 && self.g <= 0.0 && self.b <= 0.0
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.r.max(self.g).max(self.b)
+    }
+
+    /// Relative luminance with the Rec. 709 primaries.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 }
 
 impl Add<Color> for Color {
@@ -80,8 +98,8 @@ impl From<[u8; 4]> for Color {
     }
 }
 
-impl Into<[u8; 3]> for Color {
-    fn into(self) -> [u8; 3] {
-        [(self.r * 255.0) as u8, (self.g * 255.0) as u8, (self.b * 255.0) as u8]
+impl From<Color> for [u8; 3] {
+    fn from(color: Color) -> Self {
+        [(color.r * 255.0) as u8, (color.g * 255.0) as u8, (color.b * 255.0) as u8]
     }
 }
\ No newline at end of file
@@ -0,0 +1,153 @@
+use crate::{math::{Ray, RayKind}, surface::Scene, light::SceneLights, color::Color, sampling::{Rng, power_heuristic}};
+
+/// Bounces after which paths are terminated at random, with a probability that depends on their throughput.
+const ROULETTE_START: usize = 3;
+
+/// How the radiance along a camera ray is estimated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    /// Direct light from the scene lights plus perfect mirror reflections, noise free but without indirect light.
+    Whitted,
+    /// Monte Carlo path tracing with global illumination, needs many samples per pixel to converge.
+    PathTracer,
+}
+
+impl std::str::FromStr for Integrator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "whitted" => Ok(Integrator::Whitted),
+            "path" => Ok(Integrator::PathTracer),
+            _ => Err(format!("unknown integrator `{}`, expected whitted or path", s)),
+        }
+    }
+}
+
+/// Traces a path from the camera ray and returns its radiance estimate.
+///
+/// Every vertex samples each light (next-event estimation) and continues along a direction importance sampled
+/// from the BSDF. Light that both strategies can find, from lights with an area or emissive surfaces, is combined
+/// with the power heuristic. Paths end after `max_bounces` bounces, or earlier through Russian roulette.
+///
+/// The alpha returned with the radiance is zero if the camera ray misses the scene or hits a holdout, then the
+/// radiance is zero as well.
+pub fn trace_path(ray: Ray, scene: &Scene, lights: Option<&SceneLights>, max_bounces: usize, rng: &mut Rng) -> (Color, f64) {
+    let lights = lights.map(|lights| lights.as_slice()).unwrap_or_default();
+    let mut radiance: Color = 0.into();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = ray;
+    // The camera ray and specular bounces can't be found by light sampling, so emission they hit counts fully.
+    let mut specular = true;
+    let mut bsdf_pdf = 0.0;
+
+    for bounce in 0..=max_bounces {
+        let Some(intersection) = scene.intersect(&ray, f64::INFINITY) else {
+            if bounce == 0 {
+                return (0.into(), 0.0)
+            }
+            break
+        };
+        let material = scene.surfaces()[intersection.surface].get_material();
+        if bounce == 0 && material.is_holdout() {
+            return (0.into(), 0.0)
+        }
+        let (hit, normal) = (intersection.position, intersection.normal);
+        let wo = -ray.direction;
+        throughput = throughput * material.interior_transmittance(wo, normal, intersection.t);
+
+        let emitted = material.emitted_along(&ray, normal);
+        if !emitted.is_black() {
+            let weight = if specular {
+                1.0
+            } else {
+                let light_pdf = lights.iter().map(|light| light.emission_pdf(&ray, &intersection) * light.sample_count() as f64).sum();
+                power_heuristic(bsdf_pdf, light_pdf)
+            };
+            radiance = radiance + throughput * emitted * weight;
+        }
+
+        if bounce == max_bounces {
+            break
+        }
+
+        for light in lights {
+            for sample in light.samples(hit, normal, rng) {
+                if sample.pdf <= 0.0 || sample.radiance.is_black() {
+                    continue
+                }
+                let f = material.eval(wo, sample.direction, normal);
+                if f.is_black() || scene.occluded(&Ray::new(hit, sample.direction), sample.distance) {
+                    continue
+                }
+                let weight = if light.is_delta() {
+                    1.0
+                } else {
+                    power_heuristic(sample.pdf, material.pdf(wo, sample.direction, normal))
+                };
+                let cos_theta = (sample.direction * normal).abs();
+                radiance = radiance + throughput * f * sample.radiance * (cos_theta * weight / sample.pdf);
+            }
+        }
+
+        let Some(sample) = material.sample(wo, normal, rng.next_f64(), (rng.next_f64(), rng.next_f64())) else {
+            break
+        };
+        throughput = throughput * sample.weight;
+        specular = sample.specular;
+        bsdf_pdf = sample.pdf;
+        let kind = if sample.specular { RayKind::Reflection } else { RayKind::Lighting };
+        ray = Ray::new(hit, sample.direction).with_spread(ray.spread).with_kind(kind);
+
+        if bounce >= ROULETTE_START {
+            let survival = throughput.max_component().min(0.95);
+            if rng.next_f64() >= survival {
+                break
+            }
+            throughput = throughput * (1.0 / survival);
+        }
+    }
+
+    // A single degenerate path would otherwise poison the whole pixel.
+    if radiance.r.is_finite() && radiance.g.is_finite() && radiance.b.is_finite() {
+        (radiance, 1.0)
+    } else {
+        (0.into(), 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{background::{BackgroundSurface, SolidBackground}, materials::DiffuseMaterial, math::Vec3, meshes::Sphere};
+
+    #[test]
+    fn white_furnace() {
+        // A convex diffuse object under a uniform environment reflects its albedo times the environment radiance,
+        // whatever the mix of light samples and BSDF samples that found it.
+        let albedo = Color::new(0.5, 0.25, 0.75);
+        let environment = Color::new(2.0, 1.0, 0.5);
+        let background = BackgroundSurface::from_environment(SolidBackground::new(environment));
+        let lights: SceneLights = vec![Box::new(background.light())];
+        let mut scene = Scene::new();
+        scene.push(Box::new(Sphere::new(Box::new(DiffuseMaterial::new(albedo, 1.0)), Vec3::new(0.0, 0.0, 0.0), 1.0)));
+        scene.push(Box::new(background));
+
+        let mut rng = Rng::for_pixel(0, 0);
+        let samples = 2000;
+        let mut sum: Color = 0.into();
+        for i in 0..samples {
+            // Spread over the visible half of the sphere, from head on to grazing.
+            let x = 0.95 * (i as f64 + 0.5) / samples as f64;
+            let ray = Ray::new(Vec3::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).with_kind(RayKind::Camera);
+            let (color, alpha) = trace_path(ray, &scene, Some(&lights), 4, &mut rng);
+            assert_eq!(alpha, 1.0);
+            sum = sum + color;
+        }
+        let mean = sum * (1.0 / samples as f64);
+        let expected = albedo * environment;
+        for (mean, expected) in [(mean.r, expected.r), (mean.g, expected.g), (mean.b, expected.b)] {
+            assert!((mean - expected).abs() < 0.02 * expected, "{:?} != {:?}", mean, expected);
+        }
+    }
+}
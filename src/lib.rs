@@ -6,6 +6,15 @@ pub mod meshes;
 pub mod light;
 pub mod color;
 pub mod background;
+pub mod sky;
+pub mod bvh;
+pub mod obj;
+pub mod scene_file;
+pub mod sampling;
+pub mod tonemap;
+pub mod output;
+pub mod integrator;
+pub mod microfacet;
 
 pub static INV_PI: f64 = 1.0 / std::f64::consts::PI;
 pub static INV_TAU: f64 = 1.0 / (std::f64::consts::TAU);
\ No newline at end of file
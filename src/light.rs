@@ -1,14 +1,79 @@
-use crate::{math::Vec3, color::Color};
+use std::sync::Arc;
+
+use crate::{
+    math::{Vec3, Ray},
+    color::Color,
+    surface::{Surface, Scene, Intersection},
+    meshes::{Sphere, Disk, Quad},
+    materials::EmissiveMaterial,
+    sampling::{Rng, SamplePattern},
+};
 
 pub type SceneLights = Vec<Box<dyn Light>>;
 pub static EPSILON: f64 = 0.02;
 
-pub trait Light {
+pub trait Light: Send + Sync {
     fn direction(&self, hit: Vec3, normal: Vec3) -> Vec3;
 
     fn dist_to(&self, point: Vec3) -> f64;
 
     fn color(&self, position: Vec3, normal: Vec3) -> Color;
+
+    /// Picks a direction towards the light from `hit` for next-event estimation, `u` is a uniform point in the
+    /// unit square. Lights that can only be reached from a single direction return it with a pdf of one.
+    fn sample(&self, hit: Vec3, normal: Vec3, _u: (f64, f64)) -> Option<LightSample> {
+        Some(LightSample {
+            direction: self.direction(hit, normal),
+            distance: self.dist_to(hit),
+            radiance: self.color(hit, normal),
+            pdf: 1.0,
+        })
+    }
+
+    /// Solid angle density with which [`Light::sample`] picks `direction` from `origin`, zero for delta lights.
+    fn pdf(&self, _origin: Vec3, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    /// Solid angle density with which [`Light::sample`] picks the point a ray hit, zero unless `hit` lies on this
+    /// light. Multiple importance sampling weights emission a ray finds with the light it actually came from.
+    fn emission_pdf(&self, _ray: &Ray, _hit: &Intersection) -> f64 {
+        0.0
+    }
+
+    /// Whether the light is a point or a direction that random rays can never hit.
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    /// Number of points shading takes on the light at every hit, more give smoother penumbrae.
+    fn sample_count(&self) -> usize {
+        1
+    }
+
+    /// [`Light::sample_count`] stratified samples of the light as seen from `hit`, delta lights give their single
+    /// direction. The pdf of every sample includes the count, so the sum of `radiance / pdf` over them estimates the
+    /// light arriving at `hit` and multiple importance sampling weights account for all of them.
+    fn samples(&self, hit: Vec3, normal: Vec3, rng: &mut Rng) -> Vec<LightSample> {
+        if self.is_delta() {
+            return self.sample(hit, normal, (0.5, 0.5)).into_iter().collect()
+        }
+        let count = self.sample_count().max(1);
+        SamplePattern::Sobol.generate(count, rng).into_iter()
+            .filter_map(|u| self.sample(hit, normal, u))
+            .filter(|sample| sample.pdf > 0.0)
+            .map(|sample| LightSample { pdf: sample.pdf * count as f64, ..sample })
+            .collect()
+    }
+}
+
+/// A direction towards a light together with the radiance arriving along it.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f64,
+    pub radiance: Color,
+    pub pdf: f64,
 }
 
 pub struct DirectionalLight {
@@ -76,4 +141,352 @@ impl Light for PointLight {
             self.color
         }
     }
-}
\ No newline at end of file
+}
+
+/// Point light that only shines into a cone around `direction`.
+///
+/// The light is at full strength inside the inner cone and fades out smoothly towards the edge of the outer cone.
+pub struct SpotLight {
+    pub point: Vec3,
+    pub direction: Vec3,
+    color: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+    falloff: bool,
+}
+
+impl SpotLight {
+    /// Spot light with an inner cone of 30 and an outer cone of 45 degrees, measured from the axis.
+    pub fn new(point: Vec3, direction: Vec3, color: Color, brightness: f64) -> Self {
+        Self {
+            point,
+            direction: direction.normalize(),
+            color: color * brightness,
+            cos_inner: 30f64.to_radians().cos(),
+            cos_outer: 45f64.to_radians().cos(),
+            falloff: false,
+        }
+    }
+
+    /// Sets the half angles of the cones in radians, `inner` is clamped so it doesn't exceed `outer`.
+    pub fn with_cone(mut self, inner: f64, outer: f64) -> Self {
+        let outer = outer.clamp(0.0, std::f64::consts::PI);
+        self.cos_outer = outer.cos();
+        self.cos_inner = inner.clamp(0.0, outer).cos();
+        self
+    }
+
+    /// Attenuates the light with the inverse square of the distance, like [`PointLight::with_falloff`].
+    pub fn with_falloff(mut self) -> Self {
+        self.falloff = true;
+        self
+    }
+
+    /// Smoothstep from the edge of the outer cone to the inner cone.
+    fn cone_falloff(&self, cos_theta: f64) -> f64 {
+        if self.cos_inner <= self.cos_outer {
+            return if cos_theta >= self.cos_outer { 1.0 } else { 0.0 }
+        }
+        let t = ((cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn direction(&self, hit: Vec3, _normal: Vec3) -> Vec3 {
+        (self.point - hit).normalize()
+    }
+
+    fn dist_to(&self, point: Vec3) -> f64 {
+        (self.point - point).mag()
+    }
+
+    fn color(&self, position: Vec3, _normal: Vec3) -> Color {
+        let offset = position - self.point;
+        let color = self.color * self.cone_falloff(offset.normalize() * self.direction);
+        if self.falloff {
+            color * (1.0 / offset.mag2())
+        } else {
+            color
+        }
+    }
+}
+
+/// Light emitted by surfaces with an emissive material, like lamps, panels or neon shapes.
+///
+/// The radiance comes from [`Material::emitted`](crate::materials::Material::emitted) of the surfaces, which have to
+/// be added to the scene as well to be visible and cast shadows, see [`AreaLight::surfaces`]. A surface is picked in
+/// proportion to its area and sampled with [`Surface::sample_from`], surfaces without an area like planes never emit
+/// onto the scene.
+pub struct AreaLight {
+    surfaces: Vec<Arc<dyn Surface>>,
+    /// The surfaces once more, with their own BVH to find where a ray hits the light.
+    geometry: Scene,
+    /// Running sum of the surface areas, used to pick a surface proportional to its area.
+    cdf: Vec<f64>,
+    samples: usize,
+}
+
+/// Number of stratified samples shading takes from an area light unless configured otherwise.
+const DEFAULT_AREA_SAMPLES: usize = 16;
+
+impl AreaLight {
+    pub fn new<I: IntoIterator<Item = Box<dyn Surface>>>(surfaces: I) -> Self {
+        let surfaces: Vec<Arc<dyn Surface>> = surfaces.into_iter().map(Arc::from).collect();
+        let geometry = surfaces.iter().map(|surface| Box::new(surface.clone()) as Box<dyn Surface>).collect();
+        let mut total = 0.0;
+        let cdf = surfaces.iter().map(|surface| {
+            total += surface.area().unwrap_or(0.0);
+            total
+        }).collect();
+        Self { surfaces, geometry, cdf, samples: DEFAULT_AREA_SAMPLES }
+    }
+
+    /// Glowing sphere, sampled within the cone it covers as seen from the shaded point.
+    pub fn sphere(center: Vec3, radius: f64, color: Color, brightness: f64) -> Self {
+        Self::new([Box::new(Sphere::new(Box::new(EmissiveMaterial::new(color, brightness)), center, radius)) as Box<dyn Surface>])
+    }
+
+    /// Disk that shines towards `normal`.
+    pub fn disk(center: Vec3, normal: Vec3, radius: f64, color: Color, brightness: f64) -> Self {
+        Self::new([Box::new(Disk::new(Box::new(EmissiveMaterial::new(color, brightness)), center, normal, radius)) as Box<dyn Surface>])
+    }
+
+    /// Rectangle spanned by two edges from a corner, it shines towards `edge_u x edge_v`.
+    pub fn rectangle(corner: Vec3, edge_u: Vec3, edge_v: Vec3, color: Color, brightness: f64) -> Self {
+        Self::new([Box::new(Quad::new(Box::new(EmissiveMaterial::new(color, brightness)), corner, edge_u, edge_v)) as Box<dyn Surface>])
+    }
+
+    /// Sets the number of samples shading takes from the light, more give smoother penumbrae.
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Handles to the emitting surfaces for adding them to the scene.
+    pub fn surfaces(&self) -> Vec<Box<dyn Surface>> {
+        self.surfaces.iter().map(|surface| Box::new(surface.clone()) as Box<dyn Surface>).collect()
+    }
+
+    pub fn area(&self) -> f64 {
+        self.cdf.last().copied().unwrap_or(0.0)
+    }
+
+    /// Probability of picking the surface at `index`.
+    fn surface_probability(&self, index: usize) -> f64 {
+        let start = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        (self.cdf[index] - start) / self.area()
+    }
+
+    /// Radiance weighted mean direction and distance to the light from `point`, together with the radiance
+    /// arriving from all of it. This is the light as a single point light, shading uses [`Light::samples`] instead.
+    fn approximate(&self, point: Vec3) -> (Vec3, f64, Color) {
+        let mut direction = Vec3::new(0.0, 0.0, 0.0);
+        let mut distance = 0.0;
+        let mut total_weight = 0.0;
+        let mut radiance: Color = 0.into();
+        for sample in self.samples(point, Vec3::new(0.0, 0.0, 0.0), &mut Rng::for_point(point)) {
+            let contribution = sample.radiance * (1.0 / sample.pdf);
+            let weight = contribution.luminance();
+            radiance = radiance + contribution;
+            direction += sample.direction * weight;
+            distance += sample.distance * weight;
+            total_weight += weight;
+        }
+        if total_weight <= 0.0 {
+            return (Vec3::new(0.0, 1.0, 0.0), 0.0, radiance)
+        }
+        (direction.normalize(), distance / total_weight, radiance)
+    }
+}
+
+impl Light for AreaLight {
+    fn direction(&self, hit: Vec3, _normal: Vec3) -> Vec3 {
+        self.approximate(hit).0
+    }
+
+    fn dist_to(&self, point: Vec3) -> f64 {
+        let (direction, distance, _) = self.approximate(point);
+        // Shadow rays have to stop in front of the light, not at the mean distance that may lie behind its surface.
+        match self.geometry.intersect(&Ray::new(point, direction), f64::INFINITY) {
+            Some(hit) => hit.t - EPSILON,
+            None => distance,
+        }
+    }
+
+    fn color(&self, position: Vec3, _normal: Vec3) -> Color {
+        self.approximate(position).2
+    }
+
+    fn sample(&self, hit: Vec3, _normal: Vec3, u: (f64, f64)) -> Option<LightSample> {
+        let area = self.area();
+        if area <= 0.0 {
+            return None
+        }
+        let target = u.0 * area;
+        let index = self.cdf.partition_point(|&c| c <= target).min(self.cdf.len() - 1);
+        let start = if index == 0 { 0.0 } else { self.cdf[index - 1] };
+        let u0 = ((target - start) / (self.cdf[index] - start)).clamp(0.0, 1.0);
+
+        let surface = &self.surfaces[index];
+        let point = surface.sample_from(hit, (u0, u.1))?;
+        let offset = point.position - hit;
+        let distance = offset.mag();
+        if distance == 0.0 || point.pdf <= 0.0 {
+            return None
+        }
+        let direction = offset * (1.0 / distance);
+
+        Some(LightSample {
+            direction,
+            // Stop shadow rays just before the light so its own surface doesn't block them.
+            distance: distance - EPSILON,
+            radiance: surface.get_material().emitted(-direction, point.normal),
+            pdf: point.pdf * self.surface_probability(index),
+        })
+    }
+
+    fn pdf(&self, origin: Vec3, direction: Vec3) -> f64 {
+        if self.area() <= 0.0 {
+            return 0.0
+        }
+        match self.geometry.intersect(&Ray::new(origin, direction), f64::INFINITY) {
+            Some(hit) => self.surfaces[hit.surface].pdf_from(origin, &hit) * self.surface_probability(hit.surface),
+            None => 0.0,
+        }
+    }
+
+    fn emission_pdf(&self, ray: &Ray, hit: &Intersection) -> f64 {
+        if self.area() <= 0.0 {
+            return 0.0
+        }
+        // The light's surfaces are the same as in the scene, so the ray hits them at the very same distance unless
+        // something else was in front.
+        match self.geometry.intersect(ray, f64::INFINITY) {
+            Some(own) if (own.t - hit.t).abs() <= 1e-9 * hit.t.max(1.0) => {
+                self.surfaces[own.surface].pdf_from(ray.origin, &own) * self.surface_probability(own.surface)
+            },
+            _ => 0.0,
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    fn sample_count(&self) -> usize {
+        self.samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::AlbedoMaterial;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn spot_light_cone_falloff() {
+        let light = SpotLight::new(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, -2.0, 0.0), Color::new(1.0, 1.0, 1.0), 2.0);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        // Strength at unit distance, `cos_theta` from the axis of the light.
+        let strength = |cos_theta: f64| {
+            let point = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), -cos_theta, 0.0);
+            let sample = light.sample(point, normal, (0.5, 0.5)).expect("sample");
+            assert_close(sample.radiance.g, light.color(point, normal).g);
+            sample.radiance.g
+        };
+
+        let (cos_inner, cos_outer) = (30f64.to_radians().cos(), 45f64.to_radians().cos());
+        assert_close(strength(1.0), 2.0);
+        assert_close(strength(20f64.to_radians().cos()), 2.0);
+        assert_close(strength(cos_inner), 2.0);
+        // Smoothstep between the cones, half way in cosine is half the strength.
+        assert_close(strength(0.5 * (cos_inner + cos_outer)), 1.0);
+        let t = 0.25;
+        assert_close(strength(cos_outer + t * (cos_inner - cos_outer)), 2.0 * t * t * (3.0 - 2.0 * t));
+        assert_close(strength(cos_outer), 0.0);
+        assert_close(strength(60f64.to_radians().cos()), 0.0);
+        assert_close(strength(-1.0), 0.0);
+
+        // With falloff the strength drops with the square of the distance as well.
+        let light = light.with_falloff();
+        assert_close(light.color(Vec3::new(0.0, -2.0, 0.0), normal).g, 0.5);
+    }
+
+    fn ceiling_light() -> AreaLight {
+        // 2 x 2 square two units up, shining down.
+        AreaLight::rectangle(Vec3::new(-1.0, 2.0, -1.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 2.0), Color::new(1.0, 1.0, 1.0), 1.0)
+    }
+
+    #[test]
+    fn area_light_samples_are_stratified() {
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        for side in [1, 2, 4, 8] {
+            let light = ceiling_light().with_samples(side * side);
+            let samples = light.samples(origin, normal, &mut Rng::new(5));
+            assert_eq!(samples.len(), side * side);
+            // Every cell of a side x side grid over the light gets exactly one sample.
+            let mut cells = vec![0; side * side];
+            for sample in &samples {
+                let point = origin + sample.direction * (sample.distance + EPSILON);
+                assert_close(point.y, 2.0);
+                let cell = |c: f64| (((c + 1.0) * 0.5 * side as f64) as usize).min(side - 1);
+                cells[cell(point.z) * side + cell(point.x)] += 1;
+                assert_close(sample.pdf, light.pdf(origin, sample.direction) * (side * side) as f64);
+            }
+            assert!(cells.iter().all(|&count| count == 1), "{} samples: {:?}", side * side, cells);
+        }
+    }
+
+    #[test]
+    fn area_light_pdf_matches_sample() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let lights = [
+            AreaLight::sphere(Vec3::new(0.5, 3.0, 0.0), 1.0, white, 1.0),
+            AreaLight::disk(Vec3::new(0.0, 2.0, 0.5), Vec3::new(0.2, -1.0, 0.0), 1.0, white, 1.0),
+            ceiling_light(),
+        ];
+        let origin = Vec3::new(0.3, 0.0, -0.2);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        for (index, light) in lights.iter().enumerate() {
+            let n = 16;
+            for j in 0..n {
+                for i in 0..n {
+                    let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                    let sample = light.sample(origin, normal, u).expect("sample");
+                    let pdf = light.pdf(origin, sample.direction);
+                    assert!((pdf - sample.pdf).abs() <= 1e-6 * sample.pdf, "light {}: sample pdf {} != pdf {}", index, sample.pdf, pdf);
+                }
+            }
+            assert_eq!(light.pdf(origin, Vec3::new(0.0, -1.0, 0.0)), 0.0);
+        }
+    }
+
+    #[test]
+    fn area_light_penumbra() {
+        let light = ceiling_light();
+        // An opaque plane half way up covers everything with negative x.
+        let mut scene = Scene::new();
+        scene.push(Box::new(Quad::new(Box::new(AlbedoMaterial::new(Color::new(1.0, 1.0, 1.0))),
+            Vec3::new(-10.0, 1.0, -10.0), Vec3::new(10.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 20.0))));
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let visibility = |x: f64| {
+            let point = Vec3::new(x, 0.0, 0.0);
+            let samples = light.samples(point, normal, &mut Rng::for_point(point));
+            let visible = samples.iter().filter(|sample| {
+                scene.intersect(&Ray::new(point, sample.direction), sample.distance).is_none()
+            }).count();
+            visible as f64 / samples.len() as f64
+        };
+        assert_close(visibility(3.0), 1.0);
+        assert_close(visibility(-3.0), 0.0);
+        // Below the edge of the blocker it hides half of the light, a bit further out a quarter.
+        assert_close(visibility(0.0), 0.5);
+        assert_close(visibility(0.5), 0.75);
+    }
+}
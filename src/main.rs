@@ -0,0 +1,149 @@
+use std::{env, io::{self, Write}, path::Path, process, time::Instant};
+
+use raytracer::{camera::{RenderSettings, Screen}, output::OutputFormat, scene_file::load_scene, tonemap::ExportSettings};
+
+const USAGE: &str = "\
+Usage: raytracer [OPTIONS] <SCENE> <OUTPUT>
+
+Renders a JSON scene file to an image.
+
+Options:
+  -r, --resolution <WxH>  Override the resolution of the scene file
+  -b, --bounces <N>       Maximum number of reflection bounces [default: 10]
+  -s, --samples <N>       Samples per pixel [default: 1]
+  -p, --pattern <NAME>    Sample pattern: regular, jittered, halton or sobol [default: regular]
+      --filter <NAME>     Reconstruction filter: box, tent, gaussian or mitchell [default: box]
+  -i, --integrator <NAME> Light transport: whitted or path [default: whitted]
+  -t, --threads <N>       Number of render threads [default: all cores]
+  -f, --format <FORMAT>   Output format: png, png16, jpg, bmp, tga, exr, exr-half, hdr, pfm, ...
+                          [default: from the output extension]
+  -e, --exposure <EV>     Exposure adjustment in stops [default: 0]
+      --tonemap <NAME>    Tone mapping: clamp, reinhard, reinhard:<white>, aces or hable [default: clamp]
+      --linear            Write linear values instead of sRGB encoding them
+      --alpha             Write an alpha channel, transparent where camera rays miss or hit holdouts
+  -q, --quiet             Don't print progress
+      --help              Print this help";
+
+struct Options {
+    scene: String,
+    output: String,
+    resolution: Option<(usize, usize)>,
+    format: Option<OutputFormat>,
+    settings: RenderSettings,
+    export: ExportSettings,
+    quiet: bool,
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<usize, String> {
+    value.parse().map_err(|_| format!("invalid value `{}` for {}", value, flag))
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut positional = Vec::new();
+    let mut resolution = None;
+    let mut format = None;
+    let mut settings = RenderSettings::new();
+    let mut export = ExportSettings::new().with_srgb(true);
+    let mut quiet = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("missing value for {}", flag));
+
+        match arg.as_str() {
+            "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            "-r" | "--resolution" => {
+                let value = value(&arg)?;
+                let (width, height) = value.split_once('x')
+                    .ok_or_else(|| format!("invalid resolution `{}`, expected WIDTHxHEIGHT", value))?;
+                let (width, height) = (parse_number(&arg, width)?, parse_number(&arg, height)?);
+                if width == 0 || height == 0 {
+                    return Err(format!("invalid resolution `{}`", value))
+                }
+                resolution = Some((width, height));
+            },
+            "-b" | "--bounces" => settings = settings.with_max_bounces(parse_number(&arg, &value(&arg)?)?),
+            "-s" | "--samples" => settings = settings.with_samples(parse_number(&arg, &value(&arg)?)?),
+            "-p" | "--pattern" => settings = settings.with_pattern(value(&arg)?.parse()?),
+            "--filter" => settings = settings.with_filter(value(&arg)?.parse()?),
+            "-i" | "--integrator" => settings = settings.with_integrator(value(&arg)?.parse()?),
+            "-t" | "--threads" => settings = settings.with_threads(parse_number(&arg, &value(&arg)?)?),
+            "-f" | "--format" => format = Some(value(&arg)?.parse()?),
+            "-e" | "--exposure" => {
+                let value = value(&arg)?;
+                let exposure = value.parse().ok().filter(|e: &f64| e.is_finite())
+                    .ok_or_else(|| format!("invalid value `{}` for {}", value, arg))?;
+                export = export.with_exposure(exposure);
+            },
+            "--tonemap" => export = export.with_tone_map(value(&arg)?.parse()?),
+            "--linear" => export = export.with_srgb(false),
+            "--alpha" => export = export.with_alpha(true),
+            "-q" | "--quiet" => quiet = true,
+            flag if flag.starts_with('-') && flag.len() > 1 => return Err(format!("unknown option `{}`", flag)),
+            _ => positional.push(arg),
+        }
+    }
+
+    match <[String; 2]>::try_from(positional) {
+        Ok([scene, output]) => Ok(Options { scene, output, resolution, format, settings, export, quiet }),
+        Err(_) => Err(String::from("expected a scene file and an output path")),
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let start = Instant::now();
+    let mut loaded = load_scene(&options.scene).map_err(|e| e.to_string())?;
+    if !options.quiet {
+        eprintln!("Loaded {} with {} surfaces in {:.2?}", options.scene, loaded.scene.len(), start.elapsed());
+    }
+
+    if let Some((width, height)) = options.resolution {
+        // Keep the horizontal field of view and adapt the vertical one to the new aspect ratio.
+        let real_width = loaded.screen.real.0;
+        loaded.screen = Screen::new(width, height, real_width, real_width * height as f64 / width as f64);
+    }
+
+    let format = match options.format {
+        Some(format) => format,
+        None => OutputFormat::from_path(&options.output)
+            .ok_or_else(|| format!("can't tell the image format of `{}`, use --format", options.output))?,
+    };
+
+    let start = Instant::now();
+    let quiet = options.quiet;
+    let screen = loaded.camera.render_scene_with_progress(
+        &loaded.scene,
+        Some(&loaded.lights),
+        loaded.screen,
+        &options.settings,
+        |done, total| {
+            if !quiet {
+                eprint!("\rRendering {:3}% ({}/{} tiles)", done * 100 / total, done, total);
+                let _ = io::stderr().flush();
+            }
+        },
+    );
+    if !options.quiet {
+        eprintln!("\rRendered {}x{} in {:.2?} using {} threads", screen.width, screen.height, start.elapsed(), options.settings.threads);
+    }
+
+    screen.export_with(&options.output, format, &options.export).map_err(|e| format!("{}: {}", options.output, e))?;
+    if !options.quiet {
+        eprintln!("Saved {}", Path::new(&options.output).display());
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("error: {}\n\n{}", error, USAGE);
+        process::exit(2);
+    });
+
+    if let Err(error) = run(options) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
@@ -1,18 +1,182 @@
-use crate::{math::{Vec3, Ray}, surface::{Scene, HitInfo}, color::Color, light::SceneLights, INV_PI};
+use crate::{math::{Vec3, Ray, RayKind, Frame}, surface::{Scene, HitInfo}, color::Color, light::SceneLights, microfacet::{self, Ggx}, sampling::{self, Rng, SamplePattern}, INV_PI};
 
-pub trait Material {
+pub trait Material: Send + Sync {
     fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Option<HitInfo>;
 
     fn trace_shadow(&self, ray: &Ray, scene: &Scene, light_dist: f64) -> bool {
-        for surface in scene {
-            if surface.shadow_hit(ray, light_dist) {
-                return true
-            }
-        }
+        scene.occluded(ray, light_dist)
+    }
+
+    // The path tracer works with the scattering functions below, `wo` points back along the incoming ray and `wi`
+    // towards the light. Delta lobes like perfect mirrors are left out of `eval` and `pdf`, they can only be sampled.
+
+    /// BSDF value for light arriving from `wi` and leaving towards `wo`, without the cosine term.
+    fn eval(&self, _wo: Vec3, _wi: Vec3, _normal: Vec3) -> Color {
+        0.into()
+    }
+
+    /// Solid angle density with which [`Material::sample`] picks `wi`.
+    fn pdf(&self, _wo: Vec3, _wi: Vec3, _normal: Vec3) -> f64 {
+        0.0
+    }
+
+    /// Importance samples an incoming direction, `uc` is a uniform number that picks the lobe and `u` a uniform point
+    /// in the unit square that picks the direction in it. `None` ends the path.
+    fn sample(&self, _wo: Vec3, _normal: Vec3, _uc: f64, _u: (f64, f64)) -> Option<BsdfSample> {
+        None
+    }
+
+    /// Radiance the surface emits towards `wo`.
+    fn emitted(&self, _wo: Vec3, _normal: Vec3) -> Color {
+        0.into()
+    }
+
+    /// Radiance the surface emits back along `ray`, textured emitters can use the spread of the ray to prefilter.
+    fn emitted_along(&self, ray: &Ray, normal: Vec3) -> Color {
+        self.emitted(-ray.direction, normal)
+    }
+
+    /// Fraction of the light that survives a ray segment of length `distance` which reached the surface from the
+    /// inside, transmissive materials use it to absorb light in their interior.
+    fn interior_transmittance(&self, _wo: Vec3, _normal: Vec3, _distance: f64) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    /// Whether camera rays that hit the surface leave the pixel transparent, see [`HoldoutMaterial`].
+    fn is_holdout(&self) -> bool {
         false
     }
 }
 
+/// Incoming direction picked by [`Material::sample`].
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub direction: Vec3,
+    /// BSDF times cosine divided by the pdf, the factor the path throughput is multiplied with.
+    pub weight: Color,
+    pub pdf: f64,
+    /// Sampled from a delta lobe, light sampling could not have produced the direction.
+    pub specular: bool,
+}
+
+/// Whitted style direct light from every scene light, for materials that describe their scattering with
+/// [`Material::eval`].
+fn direct_lighting<M: Material + ?Sized>(material: &M, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: &SceneLights) -> Color {
+    let mut color: Color = 0.into();
+    let mut rng = Rng::for_point(hit_position);
+    for sample in lights.iter().flat_map(|light| light.samples(hit_position, hit_normal, &mut rng)) {
+        let dir = sample.direction;
+        let f = material.eval(-prev_ray.direction, dir, hit_normal);
+
+        if !f.is_black() && !material.trace_shadow(&Ray::new(hit_position, dir), scene, sample.distance) {
+            color = color + f * sample.radiance * ((hit_normal * dir).abs() / sample.pdf);
+        }
+    }
+    color
+}
+
+/// Rays a camera ray splits into at a rough surface in the Whitted integrator. Deeper bounces trace a single ray so
+/// the rays don't multiply with every bounce.
+const GLOSSY_SAMPLES: usize = 16;
+
+/// Whitted style light scattered along the directions `sample` importance samples, averaged over the rays. `sample`
+/// gets a uniform number for the lobe and a point in the unit square, like [`Material::sample`]. A delta lobe is
+/// traced with a single ray.
+fn sampled_scattering<F>(prev_ray: &Ray, hit_position: Vec3, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize, sample: F) -> Color
+where
+    F: Fn(f64, (f64, f64)) -> Option<BsdfSample>,
+{
+    let count = if prev_ray.kind == RayKind::Camera { GLOSSY_SAMPLES } else { 1 };
+    let mut rng = Rng::for_point(hit_position);
+    let mut color: Color = 0.into();
+    for u in SamplePattern::Jittered.generate(count, &mut rng) {
+        let Some(sample) = sample(rng.next_f64(), u) else { continue };
+        let ray = Ray::new(hit_position, sample.direction).with_spread(prev_ray.spread);
+        if let Some(info) = scene.hit(&ray, lights, bounce_count - 1) {
+            color = color + info.color() * sample.weight;
+        }
+        if sample.specular {
+            return color
+        }
+    }
+    color * (1.0 / count as f64)
+}
+
+/// Lambertian diffuse lobe, normalized Phong lobe around the mirror direction and a perfect mirror, the scattering
+/// behind the Whitted materials of this module.
+struct PhongBsdf {
+    diffuse: Color,
+    specular: f64,
+    exponent: f64,
+    reflection: f64,
+}
+
+impl PhongBsdf {
+    /// Probabilities to sample the diffuse, glossy and mirror lobes.
+    fn lobe_weights(&self) -> Option<[f64; 3]> {
+        let weights = [self.diffuse.max_component().max(0.0), self.specular.max(0.0), self.reflection.max(0.0)];
+        let total: f64 = weights.iter().sum();
+        (total > 0.0).then(|| weights.map(|w| w / total))
+    }
+
+    fn phong_lobe(&self, reflected: Vec3, wi: Vec3) -> f64 {
+        (reflected * wi).max(0.0).powf(self.exponent) * (self.exponent + 1.0) * INV_PI * 0.5
+    }
+
+    /// The surfaces are opaque and shaded from both sides, so the normal is flipped towards `wo`.
+    fn facing(wo: Vec3, normal: Vec3) -> Vec3 {
+        if wo * normal < 0.0 { -normal } else { normal }
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        let normal = Self::facing(wo, normal);
+        if wi * normal <= 0.0 {
+            return 0.into()
+        }
+        let reflected = (-wo).reflect(normal);
+        let glossy = self.specular * (self.exponent + 2.0) / (self.exponent + 1.0) * self.phong_lobe(reflected, wi);
+        self.diffuse * INV_PI + Color::new(glossy, glossy, glossy)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        let normal = Self::facing(wo, normal);
+        let Some([diffuse, specular, _]) = self.lobe_weights() else {
+            return 0.0
+        };
+        if wi * normal <= 0.0 {
+            return 0.0
+        }
+        let reflected = (-wo).reflect(normal);
+        diffuse * sampling::cosine_hemisphere_pdf(wi * normal) + specular * self.phong_lobe(reflected, wi)
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let normal = Self::facing(wo, normal);
+        let [diffuse, specular, reflection] = self.lobe_weights()?;
+        let reflected = (-wo).reflect(normal);
+
+        let direction = if uc < diffuse {
+            sampling::cosine_hemisphere(normal, u.0, u.1)
+        } else if uc < diffuse + specular {
+            let cos_alpha = u.0.powf(1.0 / (self.exponent + 1.0));
+            let sin_alpha = (1.0 - cos_alpha * cos_alpha).max(0.0).sqrt();
+            let phi = std::f64::consts::TAU * u.1;
+            let (tangent, bitangent) = reflected.orthonormal_basis();
+            Vec3::linear_combine(sin_alpha * phi.cos(), &tangent, sin_alpha * phi.sin(), &bitangent, cos_alpha, &reflected)
+        } else {
+            let weight = self.reflection / reflection;
+            return Some(BsdfSample { direction: reflected, weight: Color::new(weight, weight, weight), pdf: reflection, specular: true })
+        };
+
+        let cos_theta = direction * normal;
+        let pdf = self.pdf(wo, direction, normal);
+        if cos_theta <= 0.0 || pdf <= 0.0 {
+            return None
+        }
+        Some(BsdfSample { direction, weight: self.eval(wo, direction, normal) * (cos_theta / pdf), pdf, specular: false })
+    }
+}
+
 pub struct AlbedoMaterial {
     color: Color,
 }
@@ -21,24 +185,40 @@ impl AlbedoMaterial {
     pub fn new(color: Color) -> Self {
         Self { color }
     }
+
+    fn bsdf(&self) -> PhongBsdf {
+        PhongBsdf { diffuse: self.color, specular: 0.0, exponent: 1.0, reflection: 0.0 }
+    }
 }
 
 impl Material for AlbedoMaterial {
     fn calc_mat(&self, _prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, _bounce_count: usize) -> Option<HitInfo> {
         if let Some(lights) = lights {
             let mut color = self.color;
+            let mut rng = Rng::for_point(hit_position);
             for light in lights {
-                let dir = light.direction(hit_position, hit_normal);
-
-                if self.trace_shadow(
-                    &Ray::new(hit_position, dir), scene, light.dist_to(hit_position)
-                ) {
-                    color = 0.into();
-                }
+                let samples = light.samples(hit_position, hit_normal, &mut rng);
+                let visible = samples.iter().filter(|sample| {
+                    !self.trace_shadow(&Ray::new(hit_position, sample.direction), scene, sample.distance)
+                }).count();
+                // Fully shadowed by any light turns black, partly shadowed points fade with the visible fraction.
+                color = color * (visible as f64 / samples.len().max(1) as f64);
             }
             return Some(HitInfo::new(hit_position, hit_normal).tint(color))
         }
-        return Some(HitInfo::new(hit_position, hit_normal).tint(self.color)) 
+        Some(HitInfo::new(hit_position, hit_normal).tint(self.color))
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        self.bsdf().eval(wo, wi, normal)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        self.bsdf().pdf(wo, wi, normal)
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        self.bsdf().sample(wo, normal, uc, u)
     }
 }
 
@@ -54,6 +234,10 @@ impl DiffuseMaterial {
             diffuse_coeff,
         }
     }
+
+    fn bsdf(&self) -> PhongBsdf {
+        PhongBsdf { diffuse: self.color * self.diffuse_coeff, specular: 0.0, exponent: 1.0, reflection: 0.0 }
+    }
 }
 
 impl Material for DiffuseMaterial {
@@ -61,16 +245,18 @@ impl Material for DiffuseMaterial {
         let mut color: Color = 0.into();
 
         if let Some(lights) = lights {
-            for light in lights {
-                let dir = light.direction(hit_position, hit_normal);
+            let mut rng = Rng::for_point(hit_position);
+            for sample in lights.iter().flat_map(|light| light.samples(hit_position, hit_normal, &mut rng)) {
+                let dir = sample.direction;
+                let light_color = sample.radiance * (1.0 / sample.pdf);
 
                 let normal_dot_light = hit_normal * dir;
 
                 if normal_dot_light > 0.0 && !self.trace_shadow(
-                    &Ray::new(hit_position, dir), scene, light.dist_to(hit_position)
+                    &Ray::new(hit_position, dir), scene, sample.distance
                 ) {
                     let diffusion = self.diffuse_coeff * normal_dot_light * INV_PI;
-                    color = color + self.color * light.color(hit_position, hit_normal) * diffusion;
+                    color = color + self.color * light_color * diffusion;
                 }
             }
         } else {
@@ -79,6 +265,18 @@ impl Material for DiffuseMaterial {
 
         Some(HitInfo::new(hit_position, hit_normal).tint(color))
     }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        self.bsdf().eval(wo, wi, normal)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        self.bsdf().pdf(wo, wi, normal)
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        self.bsdf().sample(wo, normal, uc, u)
+    }
 }
 
 pub struct SDMaterial {
@@ -97,33 +295,36 @@ impl SDMaterial {
             exponent,
         }
     }
+
+    fn bsdf(&self) -> PhongBsdf {
+        PhongBsdf { diffuse: self.color * self.diffuse_coeff, specular: self.specular_coeff, exponent: self.exponent, reflection: 0.0 }
+    }
 }
 
 impl Material for SDMaterial {
     fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, _bounce_count: usize) -> Option<HitInfo> {
         let mut color: Color = [0, 0, 0].into();
 
-        let mut reflection_dir = hit_normal;
-        reflection_dir *= -2.0 * (prev_ray.direction * hit_normal);
-        reflection_dir += prev_ray.direction;
-        reflection_dir = reflection_dir.normalize();
+        let reflection_dir = prev_ray.direction.reflect(hit_normal).normalize();
 
         if let Some(lights) = lights {
-            for light in lights {
-                let dir = light.direction(hit_position, hit_normal);
+            let mut rng = Rng::for_point(hit_position);
+            for sample in lights.iter().flat_map(|light| light.samples(hit_position, hit_normal, &mut rng)) {
+                let dir = sample.direction;
+                let light_color = sample.radiance * (1.0 / sample.pdf);
 
                 let normal_dot_light = hit_normal * dir;
 
                 if normal_dot_light > 0.0 && !self.trace_shadow(
-                    &Ray::new(hit_position, dir), scene, light.dist_to(hit_position)
+                    &Ray::new(hit_position, dir), scene, sample.distance
                 ) {
                     let diffusion = self.diffuse_coeff * normal_dot_light * INV_PI;
-                    color = color + self.color * light.color(hit_position, hit_normal) * diffusion;
+                    color = color + self.color * light_color * diffusion;
 
                     let reflection_dot_ray = -(reflection_dir * prev_ray.direction);
                     if reflection_dot_ray > 0.0 {
                         let spec = self.specular_coeff * normal_dot_light * reflection_dot_ray.powf(self.exponent);
-                        color = color + light.color(hit_position, hit_normal) * spec;
+                        color = color + light_color * spec;
                     }
                 }
             }
@@ -133,6 +334,18 @@ impl Material for SDMaterial {
 
         Some(HitInfo::new(hit_position, hit_normal).tint(color))
     }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        self.bsdf().eval(wo, wi, normal)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        self.bsdf().pdf(wo, wi, normal)
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        self.bsdf().sample(wo, normal, uc, u)
+    }
 }
 
 pub struct SDRMaterial {
@@ -153,33 +366,41 @@ impl SDRMaterial {
             reflection_coeff,
         }
     }
+
+    fn bsdf(&self) -> PhongBsdf {
+        PhongBsdf {
+            diffuse: self.color * self.diffuse_coeff,
+            specular: self.specular_coeff,
+            exponent: self.exponent,
+            reflection: self.reflection_coeff,
+        }
+    }
 }
 
 impl Material for SDRMaterial {
     fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Option<HitInfo> {
         let mut color: Color = [0, 0, 0].into();
 
-        let mut reflection_dir = hit_normal;
-        reflection_dir *= -2.0 * (prev_ray.direction * hit_normal);
-        reflection_dir += prev_ray.direction;
-        reflection_dir = reflection_dir.normalize();
+        let reflection_dir = prev_ray.direction.reflect(hit_normal).normalize();
 
         if let Some(lights) = lights {
-            for light in lights {
-                let dir = light.direction(hit_position, hit_normal);
+            let mut rng = Rng::for_point(hit_position);
+            for sample in lights.iter().flat_map(|light| light.samples(hit_position, hit_normal, &mut rng)) {
+                let dir = sample.direction;
+                let light_color = sample.radiance * (1.0 / sample.pdf);
 
                 let normal_dot_light = hit_normal * dir;
 
                 if normal_dot_light > 0.0 && !self.trace_shadow(
-                    &Ray::new(hit_position, dir), scene, light.dist_to(hit_position)
+                    &Ray::new(hit_position, dir), scene, sample.distance
                 ) {
                     let diffusion = self.diffuse_coeff * normal_dot_light * INV_PI;
-                    color = color + self.color * light.color(hit_position, hit_normal) * diffusion;
+                    color = color + self.color * light_color * diffusion;
 
                     let reflection_dot_ray = -(reflection_dir * prev_ray.direction);
                     if reflection_dot_ray > 0.0 {
                         let spec = self.specular_coeff * normal_dot_light * reflection_dot_ray.powf(self.exponent);
-                        color = color + light.color(hit_position, hit_normal) * spec;
+                        color = color + light_color * spec;
                     }
                 }
             }
@@ -187,15 +408,11 @@ impl Material for SDRMaterial {
             color = self.color;
         }
 
-        let reflection_ray = Ray::new(hit_position, reflection_dir);
-        let mut min_distance = f64::INFINITY;
+        let reflection_ray = Ray::new(hit_position, reflection_dir).with_spread(prev_ray.spread);
         let mut result: Color = 0.into();
         if bounce_count > 0 {
-            for surface in scene {
-                if let Some(info) = surface.hit(&reflection_ray, scene, lights, bounce_count-1, min_distance) {
-                    min_distance = (info.position - hit_position).mag();
-                    result = info.color();
-                }
+            if let Some(info) = scene.hit(&reflection_ray, lights, bounce_count - 1) {
+                result = info.color();
             }
         }
 
@@ -203,4 +420,838 @@ impl Material for SDRMaterial {
 
         Some(HitInfo::new(hit_position, hit_normal).tint(color))
     }
-}
\ No newline at end of file
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        self.bsdf().eval(wo, wi, normal)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        self.bsdf().pdf(wo, wi, normal)
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        self.bsdf().sample(wo, normal, uc, u)
+    }
+}
+
+/// How the reflectance of a dielectric interface is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fresnel {
+    /// Schlick's approximation, cheaper and close to the exact value for common indices of refraction.
+    Schlick,
+    /// The Fresnel equations for unpolarized light.
+    Exact,
+}
+
+impl Fresnel {
+    /// Fraction of light reflected at an interface, `cos_i` is the cosine between the incident direction and the
+    /// normal and `eta` the ratio of the refractive index of the transmitted to that of the incident side.
+    pub fn reflectance(&self, cos_i: f64, eta: f64) -> f64 {
+        let cos_i = cos_i.clamp(0.0, 1.0);
+        let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+        if sin2_t >= 1.0 {
+            return 1.0
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+
+        match self {
+            Fresnel::Schlick => {
+                let r0 = ((eta - 1.0) / (eta + 1.0)).powi(2);
+                // Leaving the denser medium the curve has to follow the transmitted angle.
+                let cos = if eta < 1.0 { cos_t } else { cos_i };
+                r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+            },
+            Fresnel::Exact => {
+                let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+                let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+                0.5 * (parallel * parallel + perpendicular * perpendicular)
+            },
+        }
+    }
+}
+
+/// Glass, water or gemstones: reflects and refracts at the surface with the Fresnel reflectance and reflects totally
+/// past the critical angle. Closed surfaces with outward normals are expected, light travelling inside is attenuated
+/// by Beer–Lambert absorption if the material has any.
+///
+/// Rough dielectrics scatter through a GGX microfacet distribution, which blurs what is seen through them.
+pub struct DielectricMaterial {
+    ior: f64,
+    absorption: Option<Color>,
+    fresnel: Fresnel,
+    roughness: Ggx,
+}
+
+impl DielectricMaterial {
+    /// Index of refraction relative to the surrounding medium, like 1.33 for water or 1.5 for glass.
+    pub fn new(ior: f64) -> Self {
+        Self { ior, absorption: None, fresnel: Fresnel::Exact, roughness: Ggx::from_roughness(0.0) }
+    }
+
+    /// Frosted glass, `0` is perfectly smooth and `1` very rough.
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.roughness = Ggx::from_roughness(roughness);
+        self
+    }
+
+    /// Absorption coefficients per unit distance for colored glass, a channel keeps `exp(-coefficient * distance)`.
+    pub fn with_absorption(mut self, absorption: Color) -> Self {
+        self.absorption = Some(absorption);
+        self
+    }
+
+    pub fn with_fresnel(mut self, fresnel: Fresnel) -> Self {
+        self.fresnel = fresnel;
+        self
+    }
+
+    /// Normal on the side of `wo`, relative index of refraction and whether the light enters the medium.
+    fn interface(&self, wo: Vec3, normal: Vec3) -> (Vec3, f64, bool) {
+        if wo * normal >= 0.0 {
+            (normal, self.ior, true)
+        } else {
+            (-normal, 1.0 / self.ior, false)
+        }
+    }
+
+    /// Reflectance for light arriving from either side, `cos_o` is negative inside the medium.
+    fn reflectance(&self, cos_o: f64) -> f64 {
+        if cos_o < 0.0 {
+            self.fresnel.reflectance(-cos_o, 1.0 / self.ior)
+        } else {
+            self.fresnel.reflectance(cos_o, self.ior)
+        }
+    }
+
+    /// Value and sampling density of the rough BSDF, with local directions.
+    fn eval_rough(&self, wo: Vec3, wi: Vec3) -> (f64, f64) {
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return (0.0, 0.0)
+        }
+        let reflect = cos_o * cos_i > 0.0;
+        let eta = if reflect { 1.0 } else if cos_o > 0.0 { self.ior } else { 1.0 / self.ior };
+
+        // The microfacet normal that scatters wo into wi, facing outwards.
+        let half = wi * eta + wo;
+        if half.mag2() == 0.0 {
+            return (0.0, 0.0)
+        }
+        let mut m = half.normalize();
+        if m.z < 0.0 {
+            m = -m;
+        }
+        if (m * wi) * cos_i < 0.0 || (m * wo) * cos_o < 0.0 {
+            return (0.0, 0.0)
+        }
+
+        let ggx = self.roughness;
+        let reflectance = self.reflectance(wo * m);
+        if reflect {
+            let f = ggx.d(m) * ggx.g(wo, wi) * reflectance / (4.0 * cos_i * cos_o).abs();
+            let pdf = ggx.pdf_visible(wo, m) / (4.0 * (wo * m).abs()) * reflectance;
+            (f, pdf)
+        } else {
+            let denom = ((wi * m) + (wo * m) / eta).powi(2);
+            let transmittance = 1.0 - reflectance;
+            let f = ggx.d(m) * ggx.g(wo, wi) * transmittance * ((wi * m) * (wo * m) / (cos_i * cos_o * denom)).abs() / (eta * eta);
+            let pdf = ggx.pdf_visible(wo, m) * (wi * m).abs() / denom * transmittance;
+            (f, pdf)
+        }
+    }
+
+    fn sample_rough(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let frame = Frame::new(normal);
+        let wo = frame.to_local(wo);
+        let m = self.roughness.sample_visible(wo, u);
+
+        let wi = if uc < self.reflectance(wo * m) {
+            let wi = (-wo).reflect(m);
+            if wi.z * wo.z <= 0.0 {
+                return None
+            }
+            wi
+        } else {
+            // Refract through the microfacet from whichever side wo is on.
+            let (m, eta) = if wo * m < 0.0 { (-m, 1.0 / self.ior) } else { (m, self.ior) };
+            let wi = (-wo).refract(m, 1.0 / eta)?;
+            if wi.z * wo.z >= 0.0 {
+                return None
+            }
+            wi
+        };
+
+        let (f, pdf) = self.eval_rough(wo, wi);
+        if pdf <= 0.0 {
+            return None
+        }
+        let weight = f * wi.z.abs() / pdf;
+        Some(BsdfSample { direction: frame.to_world(wi), weight: Color::new(weight, weight, weight), pdf, specular: false })
+    }
+
+    fn absorb(&self, distance: f64) -> Color {
+        match self.absorption {
+            Some(absorption) => absorption.map(|a| (-a * distance).exp()),
+            None => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Material for DielectricMaterial {
+    fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Option<HitInfo> {
+        let mut color: Color = 0.into();
+
+        if bounce_count > 0 {
+            let (normal, eta, entering) = self.interface(-prev_ray.direction, hit_normal);
+            if !self.roughness.is_smooth() {
+                // Frosted glass blurs both the reflection and the refraction by sampling its microfacets.
+                let wo = -prev_ray.direction;
+                color = sampled_scattering(prev_ray, hit_position, scene, lights, bounce_count, |uc, u| self.sample(wo, hit_normal, uc, u));
+            } else {
+                let reflectance = self.fresnel.reflectance(-(prev_ray.direction * normal), eta);
+                let trace = |direction: Vec3| {
+                    scene.hit(&Ray::new(hit_position, direction).with_spread(prev_ray.spread), lights, bounce_count - 1).map_or(0.into(), |info| info.color())
+                };
+
+                color = trace(prev_ray.direction.reflect(normal)) * reflectance;
+                if reflectance < 1.0 {
+                    if let Some(refracted) = prev_ray.direction.refract(normal, 1.0 / eta) {
+                        color = color + trace(refracted) * (1.0 - reflectance);
+                    }
+                }
+            }
+
+            if !entering {
+                color = color * self.absorb((hit_position - prev_ray.origin).mag());
+            }
+        }
+
+        if let Some(lights) = lights.filter(|_| !self.roughness.is_smooth()) {
+            color = color + direct_lighting(self, prev_ray, hit_position, hit_normal, scene, lights);
+        }
+
+        Some(HitInfo::new(hit_position, hit_normal).tint(color))
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        if !self.roughness.is_smooth() {
+            return self.sample_rough(wo, normal, uc, u)
+        }
+
+        let (normal, eta, _) = self.interface(wo, normal);
+        let reflectance = self.fresnel.reflectance(wo * normal, eta);
+
+        if uc >= reflectance {
+            if let Some(direction) = (-wo).refract(normal, 1.0 / eta) {
+                // Radiance is compressed into the smaller solid angle of the denser medium.
+                let scale = 1.0 / (eta * eta);
+                return Some(BsdfSample { direction, weight: Color::new(scale, scale, scale), pdf: 1.0 - reflectance, specular: true })
+            }
+        }
+        let direction = (-wo).reflect(normal);
+        Some(BsdfSample { direction, weight: Color::new(1.0, 1.0, 1.0), pdf: reflectance, specular: true })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        if self.roughness.is_smooth() {
+            return 0.into()
+        }
+        let frame = Frame::new(normal);
+        let (f, _) = self.eval_rough(frame.to_local(wo), frame.to_local(wi));
+        Color::new(f, f, f)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        if self.roughness.is_smooth() {
+            return 0.0
+        }
+        let frame = Frame::new(normal);
+        self.eval_rough(frame.to_local(wo), frame.to_local(wi)).1
+    }
+
+    fn interior_transmittance(&self, wo: Vec3, normal: Vec3, distance: f64) -> Color {
+        if wo * normal < 0.0 {
+            self.absorb(distance)
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+}
+
+/// Metals with measured complex indices of refraction, evaluated at red, green and blue wavelengths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metal {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium,
+    Iron,
+    Chromium,
+    Titanium,
+    Platinum,
+}
+
+impl Metal {
+    /// Real part `eta` and extinction coefficient `k` of the index of refraction.
+    pub fn ior(&self) -> (Color, Color) {
+        let (eta, k) = match self {
+            Metal::Gold => ([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+            Metal::Silver => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+            Metal::Copper => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+            Metal::Aluminium => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+            Metal::Iron => ([2.911, 2.950, 2.585], [3.089, 2.932, 2.767]),
+            Metal::Chromium => ([3.107, 3.181, 2.323], [3.331, 3.329, 3.135]),
+            Metal::Titanium => ([2.741, 2.542, 2.267], [3.814, 3.435, 3.039]),
+            Metal::Platinum => ([2.376, 2.085, 1.845], [4.266, 3.715, 3.137]),
+        };
+        (Color::new(eta[0], eta[1], eta[2]), Color::new(k[0], k[1], k[2]))
+    }
+}
+
+/// Metal with a GGX microfacet distribution and the Fresnel reflectance of a conductor. Zero roughness is a perfect
+/// mirror, rougher surfaces blur the reflection and widen the highlights of the lights.
+pub struct ConductorMaterial {
+    eta: Color,
+    k: Color,
+    roughness: Ggx,
+}
+
+impl ConductorMaterial {
+    /// Conductor with the complex index of refraction `eta + i k` per channel.
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Self { eta, k, roughness: Ggx::from_roughness(roughness) }
+    }
+
+    pub fn metal(metal: Metal, roughness: f64) -> Self {
+        let (eta, k) = metal.ior();
+        Self::new(eta, k, roughness)
+    }
+
+    fn fresnel(&self, cos_i: f64) -> Color {
+        microfacet::fresnel_conductor_rgb(cos_i, self.eta, self.k)
+    }
+
+    /// Local frame around the normal flipped towards `wo`, metals are opaque and shaded from both sides.
+    fn frame(wo: Vec3, normal: Vec3) -> Frame {
+        Frame::new(if wo * normal < 0.0 { -normal } else { normal })
+    }
+}
+
+impl Material for ConductorMaterial {
+    fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Option<HitInfo> {
+        let normal = Self::frame(-prev_ray.direction, hit_normal).normal;
+        let reflectance = self.fresnel(-(prev_ray.direction * normal));
+
+        let Some(lights) = lights else {
+            return Some(HitInfo::new(hit_position, hit_normal).tint(reflectance))
+        };
+        let mut color = direct_lighting(self, prev_ray, hit_position, hit_normal, scene, lights);
+
+        if bounce_count > 0 {
+            // Rough metals blur the reflection by sampling their microfacet distribution.
+            let wo = -prev_ray.direction;
+            color = color + sampled_scattering(prev_ray, hit_position, scene, Some(lights), bounce_count, |uc, u| self.sample(wo, hit_normal, uc, u));
+        }
+
+        Some(HitInfo::new(hit_position, hit_normal).tint(color))
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        if self.roughness.is_smooth() {
+            return 0.into()
+        }
+        let frame = Self::frame(wo, normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.into()
+        }
+        let m = (wo + wi).normalize();
+        let ggx = self.roughness;
+        self.fresnel(wo * m) * (ggx.d(m) * ggx.g(wo, wi) / (4.0 * wo.z * wi.z))
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        if self.roughness.is_smooth() {
+            return 0.0
+        }
+        let frame = Self::frame(wo, normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0
+        }
+        let m = (wo + wi).normalize();
+        self.roughness.pdf_visible(wo, m) / (4.0 * (wo * m).abs())
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, _uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let frame = Self::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z <= 0.0 {
+            return None
+        }
+
+        if self.roughness.is_smooth() {
+            let direction = (-wo).reflect(frame.normal);
+            return Some(BsdfSample { direction, weight: self.fresnel(wo_local.z), pdf: 1.0, specular: true })
+        }
+
+        let m = self.roughness.sample_visible(wo_local, u);
+        let wi = (-wo_local).reflect(m);
+        if wi.z <= 0.0 {
+            return None
+        }
+        let direction = frame.to_world(wi);
+        let pdf = self.pdf(wo, direction, normal);
+        if pdf <= 0.0 {
+            return None
+        }
+        Some(BsdfSample { direction, weight: self.eval(wo, direction, normal) * (wi.z / pdf), pdf, specular: false })
+    }
+}
+
+/// Lower bound for the roughness of the principled lobes, smoother ones would need delta lobes.
+const MIN_PRINCIPLED_ROUGHNESS: f64 = 0.05;
+
+/// `(1 - cos)^5`, the weight of Schlick's Fresnel approximation.
+fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+/// Disney style principled material with the parameters of the glTF metallic-roughness model.
+///
+/// A diffuse and a sheen lobe for the dielectric base, a GGX specular lobe that takes the base color as its
+/// reflectance as the material gets metallic, rough glass for transmission and a clear coat layer on top of it all.
+/// Parameters are in `[0, 1]` and the defaults match glTF: a rough non-metal with 4% reflectance at normal incidence.
+#[derive(Debug, Clone)]
+pub struct PrincipledMaterial {
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    clearcoat: f64,
+    clearcoat_roughness: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    transmission: f64,
+    ior: f64,
+    emission: Color,
+}
+
+impl PrincipledMaterial {
+    pub fn new(base_color: Color) -> Self {
+        Self {
+            base_color,
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.03,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            transmission: 0.0,
+            ior: 1.5,
+            emission: 0.into(),
+        }
+    }
+
+    pub fn with_metallic(mut self, metallic: f64) -> Self {
+        self.metallic = metallic.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_roughness(mut self, roughness: f64) -> Self {
+        self.roughness = roughness.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Reflectance of the dielectric base, `0.5` is 4% at normal incidence.
+    pub fn with_specular(mut self, specular: f64) -> Self {
+        self.specular = specular.max(0.0);
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: f64, roughness: f64) -> Self {
+        self.clearcoat = clearcoat.clamp(0.0, 1.0);
+        self.clearcoat_roughness = roughness.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Retro-reflective rim for cloth, `tint` blends its color from white to the hue of the base color.
+    pub fn with_sheen(mut self, sheen: f64, tint: f64) -> Self {
+        self.sheen = sheen.max(0.0);
+        self.sheen_tint = tint.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: f64) -> Self {
+        self.transmission = transmission.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_ior(mut self, ior: f64) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    /// Emitted radiance.
+    pub fn with_emission(mut self, emission: Color) -> Self {
+        self.emission = emission;
+        self
+    }
+
+    fn specular_ggx(&self) -> Ggx {
+        Ggx::from_roughness(self.roughness.max(MIN_PRINCIPLED_ROUGHNESS))
+    }
+
+    fn clearcoat_ggx(&self) -> Ggx {
+        Ggx::from_roughness(self.clearcoat_roughness.max(MIN_PRINCIPLED_ROUGHNESS))
+    }
+
+    fn glass(&self) -> DielectricMaterial {
+        DielectricMaterial::new(self.ior).with_roughness(self.roughness.max(MIN_PRINCIPLED_ROUGHNESS))
+    }
+
+    fn specular_f0(&self) -> Color {
+        let dielectric = 0.08 * self.specular;
+        lerp(Color::new(dielectric, dielectric, dielectric), self.base_color, self.metallic)
+    }
+
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    fn transmission_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    /// Probabilities to sample the diffuse, specular, transmission and clear coat lobes, estimated from their
+    /// reflectance towards `cos_o`.
+    fn lobe_probabilities(&self, cos_o: f64) -> Option<[f64; 4]> {
+        let fresnel = schlick_weight(cos_o);
+        let f0 = self.specular_f0().luminance();
+        let weights = [
+            self.diffuse_weight() * self.base_color.luminance().max(0.01),
+            (f0 + (1.0 - f0) * fresnel).max(0.01),
+            self.transmission_weight(),
+            self.clearcoat * (0.04 + 0.96 * fresnel),
+        ];
+        let total: f64 = weights.iter().sum();
+        (total > 0.0).then(|| weights.map(|w| w / total))
+    }
+
+    /// The reflecting lobes for local directions above the surface.
+    fn eval_reflection(&self, wo: Vec3, wi: Vec3) -> Color {
+        let m = (wo + wi).normalize();
+        let cos_d = wi * m;
+        let white = Color::new(1.0, 1.0, 1.0);
+
+        // Burley's diffuse with its grazing retro-reflection, plus sheen.
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let diffuse = self.base_color * (INV_PI * (1.0 + (fd90 - 1.0) * schlick_weight(wi.z)) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z)));
+        let luminance = self.base_color.luminance();
+        let tint = if luminance > 0.0 { self.base_color * (1.0 / luminance) } else { white };
+        let sheen = lerp(white, tint, self.sheen_tint) * (self.sheen * schlick_weight(cos_d));
+
+        let ggx = self.specular_ggx();
+        let fresnel = lerp(self.specular_f0(), white, schlick_weight(cos_d));
+        let specular = fresnel * (ggx.d(m) * ggx.g(wo, wi) / (4.0 * wo.z * wi.z));
+
+        // The specular lobe reflects for the glass as well, which only adds the light it transmits.
+        let base = (diffuse + sheen) * self.diffuse_weight() + specular;
+        if self.clearcoat <= 0.0 {
+            return base
+        }
+
+        let coat = self.clearcoat_ggx();
+        let coat_fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+        let clearcoat = self.clearcoat * coat_fresnel * coat.d(m) * coat.g(wo, wi) / (4.0 * wo.z * wi.z);
+        // Light reflected by the coat doesn't reach the base.
+        base * (1.0 - self.clearcoat * (0.04 + 0.96 * schlick_weight(wo.z))) + Color::new(clearcoat, clearcoat, clearcoat)
+    }
+
+    /// Local frame around the normal flipped towards `wo`, the reflecting lobes are shaded from both sides.
+    fn frame(wo: Vec3, normal: Vec3) -> Frame {
+        Frame::new(if wo * normal < 0.0 { -normal } else { normal })
+    }
+}
+
+impl Material for PrincipledMaterial {
+    fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Option<HitInfo> {
+        let Some(lights) = lights else {
+            return Some(HitInfo::new(hit_position, hit_normal).tint(self.base_color + self.emission))
+        };
+        let mut color = self.emission + direct_lighting(self, prev_ray, hit_position, hit_normal, scene, lights);
+
+        if bounce_count > 0 {
+            let normal = Self::frame(-prev_ray.direction, hit_normal).normal;
+            let fresnel = schlick_weight(-(prev_ray.direction * normal));
+            let white = Color::new(1.0, 1.0, 1.0);
+            let coat = self.clearcoat * (0.04 + 0.96 * fresnel);
+            let reflectance = lerp(self.specular_f0(), white, fresnel) * (1.0 - coat) + white * coat;
+
+            let reflection_ray = Ray::new(hit_position, prev_ray.direction.reflect(normal)).with_spread(prev_ray.spread);
+            if let Some(info) = scene.hit(&reflection_ray, Some(lights), bounce_count - 1) {
+                color = color + info.color() * reflectance;
+            }
+
+            if self.transmission_weight() > 0.0 {
+                // Only the refracted part of the glass, its reflection is already part of the specular lobe above.
+                let glass = self.glass();
+                let wo = -prev_ray.direction;
+                let transmittance = 1.0 - glass.reflectance(wo * hit_normal);
+                let refracted = sampled_scattering(prev_ray, hit_position, scene, Some(lights), bounce_count, |_, u| {
+                    glass.sample(wo, hit_normal, 1.0, u).filter(|sample| (sample.direction * hit_normal) * (wo * hit_normal) < 0.0)
+                });
+                color = color + refracted * self.base_color * (transmittance * self.transmission_weight());
+            }
+        }
+
+        Some(HitInfo::new(hit_position, hit_normal).tint(color))
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        let mut f: Color = 0.into();
+        // Only the transmitted part of the glass, its reflection is already part of the specular lobe.
+        if self.transmission_weight() > 0.0 && (wo * normal) * (wi * normal) < 0.0 {
+            f = self.glass().eval(wo, wi, normal) * self.base_color * self.transmission_weight();
+        }
+
+        let frame = Self::frame(wo, normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z > 0.0 && wi.z > 0.0 {
+            f = f + self.eval_reflection(wo, wi);
+        }
+        f
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        let frame = Self::frame(wo, normal);
+        let (wo_local, wi_local) = (frame.to_local(wo), frame.to_local(wi));
+        let Some([diffuse, specular, transmission, clearcoat]) = self.lobe_probabilities(wo_local.z) else {
+            return 0.0
+        };
+
+        let mut pdf = 0.0;
+        if transmission > 0.0 {
+            pdf += transmission * self.glass().pdf(wo, wi, normal);
+        }
+        if wo_local.z > 0.0 && wi_local.z > 0.0 {
+            let m = (wo_local + wi_local).normalize();
+            let jacobian = 1.0 / (4.0 * (wo_local * m).abs());
+            pdf += diffuse * sampling::cosine_hemisphere_pdf(wi_local.z)
+                + specular * self.specular_ggx().pdf_visible(wo_local, m) * jacobian
+                + clearcoat * self.clearcoat_ggx().pdf_visible(wo_local, m) * jacobian;
+        }
+        pdf
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        let frame = Self::frame(wo, normal);
+        let wo_local = frame.to_local(wo);
+        let [diffuse, specular, transmission, _] = self.lobe_probabilities(wo_local.z)?;
+
+        let direction = if uc < diffuse {
+            sampling::cosine_hemisphere(frame.normal, u.0, u.1)
+        } else if uc < diffuse + specular + transmission && uc >= diffuse + specular {
+            let uc = (uc - diffuse - specular) / transmission;
+            self.glass().sample(wo, normal, uc, u)?.direction
+        } else {
+            let ggx = if uc < diffuse + specular { self.specular_ggx() } else { self.clearcoat_ggx() };
+            let m = ggx.sample_visible(wo_local, u);
+            frame.to_world((-wo_local).reflect(m))
+        };
+
+        let pdf = self.pdf(wo, direction, normal);
+        if pdf <= 0.0 {
+            return None
+        }
+        let weight = self.eval(wo, direction, normal) * ((direction * normal).abs() / pdf);
+        Some(BsdfSample { direction, weight, pdf, specular: false })
+    }
+
+    fn emitted(&self, _wo: Vec3, _normal: Vec3) -> Color {
+        self.emission
+    }
+}
+
+/// Surface that emits light and reflects none, it turns any surface into a lamp. Add the surface to an
+/// [`AreaLight`](crate::light::AreaLight) as well so that it lights the rest of the scene.
+#[derive(Debug, Clone)]
+pub struct EmissiveMaterial {
+    radiance: Color,
+    two_sided: bool,
+}
+
+impl EmissiveMaterial {
+    pub fn new(color: Color, brightness: f64) -> Self {
+        Self { radiance: color * brightness, two_sided: false }
+    }
+
+    /// Emits from the back of the surface too, instead of only towards where the normal points.
+    pub fn with_two_sided(mut self) -> Self {
+        self.two_sided = true;
+        self
+    }
+}
+
+impl Material for EmissiveMaterial {
+    fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, _scene: &Scene, _lights: Option<&SceneLights>, _bounce_count: usize) -> Option<HitInfo> {
+        Some(HitInfo::new(hit_position, hit_normal).tint(self.emitted(-prev_ray.direction, hit_normal)))
+    }
+
+    fn emitted(&self, wo: Vec3, normal: Vec3) -> Color {
+        if self.two_sided || wo * normal > 0.0 {
+            self.radiance
+        } else {
+            0.into()
+        }
+    }
+}
+
+/// Holdout or matte object for compositing. Camera rays that hit it leave a transparent hole with zero color and
+/// alpha, for the plate the render goes over to show through. All other rays see `material`, so the object still
+/// casts shadows and shows up in reflections.
+pub struct HoldoutMaterial {
+    material: Box<dyn Material>,
+}
+
+impl HoldoutMaterial {
+    pub fn new(material: Box<dyn Material>) -> Self {
+        Self { material }
+    }
+}
+
+impl Material for HoldoutMaterial {
+    fn calc_mat(&self, prev_ray: &Ray, hit_position: Vec3, hit_normal: Vec3, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize) -> Option<HitInfo> {
+        self.material.calc_mat(prev_ray, hit_position, hit_normal, scene, lights, bounce_count)
+    }
+
+    fn trace_shadow(&self, ray: &Ray, scene: &Scene, light_dist: f64) -> bool {
+        self.material.trace_shadow(ray, scene, light_dist)
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> Color {
+        self.material.eval(wo, wi, normal)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, normal: Vec3) -> f64 {
+        self.material.pdf(wo, wi, normal)
+    }
+
+    fn sample(&self, wo: Vec3, normal: Vec3, uc: f64, u: (f64, f64)) -> Option<BsdfSample> {
+        self.material.sample(wo, normal, uc, u)
+    }
+
+    fn emitted(&self, wo: Vec3, normal: Vec3) -> Color {
+        self.material.emitted(wo, normal)
+    }
+
+    fn emitted_along(&self, ray: &Ray, normal: Vec3) -> Color {
+        self.material.emitted_along(ray, normal)
+    }
+
+    fn interior_transmittance(&self, wo: Vec3, normal: Vec3, distance: f64) -> Color {
+        self.material.interior_transmittance(wo, normal, distance)
+    }
+
+    fn is_holdout(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meshes::Sphere;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn fresnel_normal_incidence_and_total_internal_reflection() {
+        // ((n - 1) / (n + 1))^2 head on, from either side of the interface.
+        for fresnel in [Fresnel::Exact, Fresnel::Schlick] {
+            assert_close(fresnel.reflectance(1.0, 1.5), 0.04);
+            assert_close(fresnel.reflectance(1.0, 1.0 / 1.5), 0.04);
+            // Past the critical angle of about 41.8 degrees everything is reflected inside glass.
+            assert_close(fresnel.reflectance(0.7, 1.0 / 1.5), 1.0);
+            assert!(fresnel.reflectance(0.8, 1.0 / 1.5) < 1.0);
+        }
+
+        // Leaving glass at 60 degrees from the normal, even a sample that would pick refraction reflects.
+        let glass = DielectricMaterial::new(1.5);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let wo = Vec3::new(3f64.sqrt() / 2.0, 0.0, -0.5);
+        let sample = glass.sample(wo, normal, 0.999, (0.5, 0.5)).expect("reflection");
+        assert!(sample.specular);
+        assert_close(sample.pdf, 1.0);
+        assert_close(sample.direction.x, -wo.x);
+        assert_close(sample.direction.z, wo.z);
+    }
+
+    #[test]
+    fn rough_conductor_blurs_reflection() {
+        // A small white ball in the mirror direction of a camera ray hitting the metal at the origin.
+        let mut scene = Scene::new();
+        scene.push(Box::new(Sphere::new(Box::new(AlbedoMaterial::new(Color::new(1.0, 1.0, 1.0))), Vec3::new(0.0, 5.0, 5.0), 0.5)));
+        let lights: SceneLights = Vec::new();
+        let ray = Ray::new(Vec3::new(0.0, -5.0, 5.0), Vec3::new(0.0, 1.0, -1.0).normalize()).with_kind(RayKind::Camera);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let reflection = |roughness: f64| {
+            let metal = ConductorMaterial::metal(Metal::Silver, roughness);
+            metal.calc_mat(&ray, Vec3::new(0.0, 0.0, 0.0), normal, &scene, Some(&lights), 1).expect("hit").color()
+        };
+
+        let smooth = reflection(0.0);
+        assert_close(smooth.g, ConductorMaterial::metal(Metal::Silver, 0.0).fresnel(0.5f64.sqrt()).g);
+        // Most of the rays of a rough surface miss the ball.
+        let rough = reflection(1.0);
+        assert!(rough.g < 0.25 * smooth.g, "rough {:?}, smooth {:?}", rough, smooth);
+        let glossy = reflection(0.2);
+        assert!(glossy.g < smooth.g && glossy.g > rough.g, "glossy {:?}, rough {:?}, smooth {:?}", glossy, rough, smooth);
+    }
+
+    #[test]
+    fn rough_dielectric_blurs_transmission() {
+        // Head on through the glass at the origin, a white ball behind it that is either small or fills the view.
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).with_kind(RayKind::Camera);
+        let lights: SceneLights = Vec::new();
+        let transmitted = |roughness: f64, radius: f64| {
+            let mut scene = Scene::new();
+            scene.push(Box::new(Sphere::new(Box::new(AlbedoMaterial::new(Color::new(1.0, 1.0, 1.0))), Vec3::new(0.0, 0.0, -10.0), radius)));
+            let glass = DielectricMaterial::new(1.5).with_roughness(roughness);
+            glass.calc_mat(&ray, Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), &scene, Some(&lights), 1).expect("hit").color().g
+        };
+
+        // Smooth glass shows the small ball as bright as the large one, rough glass scatters most rays past it.
+        let smooth = transmitted(0.0, 0.5) / transmitted(0.0, 9.0);
+        assert_close(smooth, 1.0);
+        let rough = transmitted(0.5, 0.5) / transmitted(0.5, 9.0);
+        assert!(rough < 0.5, "rough {}", rough);
+    }
+
+    #[test]
+    fn principled_glass_reflects_once() {
+        // Head on, a white ball in front of the glass shows up in its reflection and one behind it through it.
+        let glass = PrincipledMaterial::new(Color::new(1.0, 1.0, 1.0)).with_transmission(1.0).with_roughness(0.0);
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0)).with_kind(RayKind::Camera);
+        let lights: SceneLights = Vec::new();
+        let shade = |ball: Vec3| {
+            let mut scene = Scene::new();
+            scene.push(Box::new(Sphere::new(Box::new(AlbedoMaterial::new(Color::new(1.0, 1.0, 1.0))), ball, 3.0)));
+            glass.calc_mat(&ray, Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), &scene, Some(&lights), 1).expect("hit").color()
+        };
+
+        let reflected = shade(Vec3::new(0.0, 0.0, 10.0)).g;
+        assert!((reflected - 0.04).abs() < 0.002, "reflected {}", reflected);
+        // The rest is refracted, its radiance compressed into the smaller solid angle inside the glass.
+        let refracted = shade(Vec3::new(0.0, 0.0, -10.0)).g;
+        let expected = 0.96 / (1.5 * 1.5);
+        assert!((refracted - expected).abs() < 0.02 * expected, "refracted {}, expected {}", refracted, expected);
+    }
+}
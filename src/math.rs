@@ -1,4 +1,4 @@
-use std::ops::{Add, Mul, Div, Sub, MulAssign, AddAssign, Neg};
+use std::ops::{Add, Mul, Div, Sub, MulAssign, AddAssign, Neg, Index};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Vec3 {
@@ -37,6 +37,44 @@ impl Vec3 {
         }
     }
 
+    pub fn min(self, other: Self) -> Self {
+        Self::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Self::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    /// Mirrors the vector at the plane with the given normal, `self` points towards the surface.
+    pub fn reflect(self, normal: Self) -> Self {
+        self - normal * (2.0 * self.dot(normal))
+    }
+
+    /// Bends the unit vector at an interface with the given normal by Snell's law, `self` points towards the surface,
+    /// `normal` against it and `eta` is the ratio of the refractive indices of the incident and the transmitted side.
+    /// `None` on total internal reflection.
+    pub fn refract(self, normal: Self, eta: f64) -> Option<Self> {
+        let cos_i = -self.dot(normal);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+        if sin2_t >= 1.0 {
+            return None
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(self * eta + normal * (eta * cos_i - cos_t))
+    }
+
+    /// Two unit vectors that form an orthonormal basis together with `self`, which has to be normalized.
+    pub fn orthonormal_basis(self) -> (Self, Self) {
+        // Duff et al., "Building an Orthonormal Basis, Revisited"
+        let sign = 1f64.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Self::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Self::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
+
     pub fn linear_combine(
         a: f64, vec_a: &Vec3,
         b: f64, vec_b: &Vec3,
@@ -130,23 +168,157 @@ impl Div<f64> for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &Self::Output {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 axis out of range: {}", axis),
+        }
+    }
+}
+
 impl From<(f64, f64, f64)> for Vec3 {
     fn from(vec: (f64, f64, f64)) -> Self {
         Self { x: vec.0, y: vec.1, z: vec.2 }
     }
 }
 
+/// What a ray is traced for, backgrounds can be hidden from some kinds of rays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RayKind {
+    /// Leaves the camera.
+    Camera,
+    /// Mirrors, refracts or scatters off a specular lobe, what reflections show.
+    #[default]
+    Reflection,
+    /// Scattered off a diffuse or glossy lobe to gather the light arriving at a surface.
+    Lighting,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// Angle in radians by which the cone of directions the ray stands for widens, 0 for an infinitely thin ray.
+    /// Textures that are far away use it to pick how much to prefilter.
+    pub spread: f64,
+    pub kind: RayKind,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
         Self {
             origin,
-            direction: direction.normalize()
+            direction: direction.normalize(),
+            spread: 0.0,
+            kind: RayKind::default(),
         }
     }
-}
\ No newline at end of file
+
+    pub fn with_spread(mut self, spread: f64) -> Self {
+        self.spread = spread;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: RayKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Point at distance `t` along the ray.
+    pub fn at(&self, t: f64) -> Vec3 {
+        self.origin + self.direction * t
+    }
+}
+
+/// Orthonormal basis around a normal, local coordinates have the normal as their z axis.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    pub normal: Vec3,
+}
+
+impl Frame {
+    pub fn new(normal: Vec3) -> Self {
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Self { tangent, bitangent, normal }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.tangent), v.dot(self.bitangent), v.dot(self.normal))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        Vec3::linear_combine(v.x, &self.tangent, v.y, &self.bitangent, v.z, &self.normal)
+    }
+}
+
+/// Axis-aligned bounding box, used by the acceleration structures to cull rays.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min: min.min(max), max: min.max(max) }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Self { min: self.min.min(point), max: self.max.max(point) }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let e = self.extent();
+        if e.x < 0.0 || e.y < 0.0 || e.z < 0.0 {
+            return 0.0
+        }
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    /// Slab test, returns the distance at which the ray enters the box if that is closer than `max_distance`.
+    pub fn intersect(&self, ray: &Ray, inv_direction: Vec3, max_distance: f64) -> Option<f64> {
+        let mut t_min = 0.0f64;
+        let mut t_max = max_distance;
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - ray.origin[axis]) * inv_direction[axis];
+            let t1 = (self.max[axis] - ray.origin[axis]) * inv_direction[axis];
+            let (near, far) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
+
+            // NaN (0 * inf) means the ray lies inside the slab plane, which must not cull the box.
+            if near > t_min { t_min = near; }
+            if far < t_max { t_max = far; }
+            if t_min > t_max {
+                return None
+            }
+        }
+
+        Some(t_min)
+    }
+}
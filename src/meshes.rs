@@ -1,4 +1,6 @@
-use crate::{surface::Surface, materials::Material, math::{Vec3, self}, light::EPSILON};
+use std::{fmt, sync::Arc};
+
+use crate::{surface::{Surface, Intersection, SurfaceSample}, materials::Material, math::{Vec3, Aabb, self}, light::EPSILON, sampling, INV_PI, INV_TAU};
 
 pub struct Plane {
     pub material: Box<dyn Material>,
@@ -13,27 +15,27 @@ impl Plane {
 }
 
 impl Surface for Plane {
-    fn get_material(&self) -> &Box<dyn crate::materials::Material> {
-        &self.material
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
     }
 
-    fn surface_hit(&self, ray: &crate::math::Ray, min_distance: f64) -> Option<crate::math::Vec3> {
+    fn intersect(&self, ray: &math::Ray, max_distance: f64) -> Option<Intersection> {
         let dn = ray.direction * self.normal;
 
         if dn != 0.0 {
             let t = (self.point - ray.origin) * self.normal / dn;
 
-            if t > EPSILON && t < min_distance {
-                return Some(ray.origin + ray.direction * t)
+            if t > EPSILON && t < max_distance {
+                // World space coordinates along two axes of the plane.
+                let hit = Intersection::new(ray, t, self.normal);
+                let (tangent, bitangent) = self.normal.orthonormal_basis();
+                let offset = hit.position - self.point;
+                return Some(hit.with_uv((offset * tangent, offset * bitangent)))
             }
         }
         None
     }
 
-    fn get_normal(&self, _hit: &Vec3) -> Vec3 {
-        self.normal
-    }
-
     fn shadow_hit(&self, ray: &math::Ray, light_dist: f64) -> bool {
         let dn = ray.direction * self.normal;
 
@@ -61,11 +63,11 @@ impl Sphere {
 }
 
 impl Surface for Sphere {
-    fn get_material(&self) -> &Box<dyn Material> {
-        &self.material
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
     }
 
-    fn surface_hit(&self, ray: &crate::math::Ray, min_distance: f64) -> Option<Vec3> {
+    fn intersect(&self, ray: &math::Ray, max_distance: f64) -> Option<Intersection> {
         let aux = ray.origin - self.origin;
 
         let d_sqr = ray.direction.mag2();
@@ -80,22 +82,72 @@ impl Surface for Sphere {
 
             let mut t = -p_half - sqrt_discriminant;
 
-            if !(t > EPSILON && t < min_distance) {
+            if !(t > EPSILON && t < max_distance) {
                 t = -p_half + sqrt_discriminant;
 
-                if !(t > EPSILON && t < min_distance) {
+                if !(t > EPSILON && t < max_distance) {
                     return None
                 }
             }
 
-            return Some(ray.origin + ray.direction * t)
+            let position = ray.at(t);
+            let normal = (position - self.origin).normalize();
+            // Longitude and latitude, v grows from the south to the north pole.
+            let u = 0.5 + normal.z.atan2(normal.x) * INV_TAU;
+            let v = 1.0 - normal.y.clamp(-1.0, 1.0).acos() * INV_PI;
+            return Some(Intersection::new(ray, t, normal).with_uv((u, v)))
         }
 
         None
     }
 
-    fn get_normal(&self, hit: &Vec3) -> Vec3 {
-        (*hit - self.origin).normalize()
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.origin - r, self.origin + r))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(2.0 * std::f64::consts::TAU * self.radius * self.radius)
+    }
+
+    fn sample_point(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        let normal = sampling::uniform_sphere(u.0, u.1);
+        Some((self.origin + normal * self.radius, normal))
+    }
+
+    /// Samples the cone of directions in which the sphere is seen from `origin`, so every point is a visible one.
+    fn sample_from(&self, origin: Vec3, u: (f64, f64)) -> Option<SurfaceSample> {
+        let to_center = self.origin - origin;
+        let distance_sqr = to_center.mag2();
+        let sin_max_sqr = self.radius * self.radius / distance_sqr;
+        if sin_max_sqr >= 1.0 {
+            return None
+        }
+        let distance = distance_sqr.sqrt();
+        let cos_max = (1.0 - sin_max_sqr).sqrt();
+        // 1 - cos_max written so it doesn't cancel out for small, distant spheres.
+        let solid_angle = std::f64::consts::TAU * sin_max_sqr / (1.0 + cos_max);
+
+        let cos_theta = 1.0 - u.0 * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = std::f64::consts::TAU * u.1;
+        let frame = math::Frame::new(to_center * (1.0 / distance));
+        let direction = frame.to_world(Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+
+        // Near intersection of the direction with the sphere.
+        let t = distance * cos_theta - (self.radius * self.radius - distance_sqr * sin_theta * sin_theta).max(0.0).sqrt();
+        let position = origin + direction * t;
+        let normal = (position - self.origin).normalize();
+        Some(SurfaceSample { position, normal, pdf: 1.0 / solid_angle })
+    }
+
+    fn pdf_from(&self, origin: Vec3, _hit: &Intersection) -> f64 {
+        let sin_max_sqr = self.radius * self.radius / (self.origin - origin).mag2();
+        if sin_max_sqr >= 1.0 {
+            return 0.0
+        }
+        let cos_max = (1.0 - sin_max_sqr).sqrt();
+        (1.0 + cos_max) / (std::f64::consts::TAU * sin_max_sqr)
     }
 
     fn shadow_hit(&self, ray: &math::Ray, light_dist: f64) -> bool {
@@ -126,4 +178,501 @@ impl Surface for Sphere {
 
         false
     }
-}
\ No newline at end of file
+}
+
+/// Parallelogram spanned by two edges from a corner, the normal `edge_u x edge_v` gives its front side.
+pub struct Quad {
+    pub material: Box<dyn Material>,
+    corner: Vec3,
+    edge_u: Vec3,
+    edge_v: Vec3,
+    normal: Vec3,
+}
+
+impl Quad {
+    pub fn new(material: Box<dyn Material>, corner: Vec3, edge_u: Vec3, edge_v: Vec3) -> Self {
+        Self { material, corner, edge_u, edge_v, normal: edge_u.cross(edge_v).normalize() }
+    }
+
+    /// Distance along the ray and the coordinates of the hit along both edges.
+    fn intersect_quad(&self, ray: &math::Ray, max_distance: f64) -> Option<(f64, f64, f64)> {
+        let dn = ray.direction * self.normal;
+        if dn == 0.0 {
+            return None
+        }
+        let t = (self.corner - ray.origin) * self.normal / dn;
+        if !(t > EPSILON && t < max_distance) {
+            return None
+        }
+
+        let offset = ray.at(t) - self.corner;
+        let n = self.edge_u.cross(self.edge_v);
+        let inv_n2 = 1.0 / n.mag2();
+        let u = offset.cross(self.edge_v) * n * inv_n2;
+        let v = self.edge_u.cross(offset) * n * inv_n2;
+        ((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)).then_some((t, u, v))
+    }
+}
+
+impl Surface for Quad {
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn intersect(&self, ray: &math::Ray, max_distance: f64) -> Option<Intersection> {
+        let (t, u, v) = self.intersect_quad(ray, max_distance)?;
+        Some(Intersection::new(ray, t, self.normal).with_uv((u, v)))
+    }
+
+    fn shadow_hit(&self, ray: &math::Ray, light_dist: f64) -> bool {
+        self.intersect_quad(ray, light_dist).is_some()
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let (corner, u, v) = (self.corner, self.edge_u, self.edge_v);
+        Some(Aabb::new(corner, corner).grow(corner + u).grow(corner + v).grow(corner + u + v))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(self.edge_u.cross(self.edge_v).mag())
+    }
+
+    fn sample_point(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        Some((self.corner + self.edge_u * u.0 + self.edge_v * u.1, self.normal))
+    }
+}
+
+/// Flat disk around `center`, its front side faces towards `normal`.
+pub struct Disk {
+    pub material: Box<dyn Material>,
+    center: Vec3,
+    normal: Vec3,
+    radius: f64,
+}
+
+impl Disk {
+    pub fn new(material: Box<dyn Material>, center: Vec3, normal: Vec3, radius: f64) -> Self {
+        Self { material, center, normal: normal.normalize(), radius }
+    }
+
+    fn intersect_disk(&self, ray: &math::Ray, max_distance: f64) -> Option<f64> {
+        let dn = ray.direction * self.normal;
+        if dn == 0.0 {
+            return None
+        }
+        let t = (self.center - ray.origin) * self.normal / dn;
+        (t > EPSILON && t < max_distance && (ray.at(t) - self.center).mag2() <= self.radius * self.radius).then_some(t)
+    }
+}
+
+impl Surface for Disk {
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn intersect(&self, ray: &math::Ray, max_distance: f64) -> Option<Intersection> {
+        let t = self.intersect_disk(ray, max_distance)?;
+        // Polar coordinates, the distance from the center relative to the radius and the angle around it.
+        let hit = Intersection::new(ray, t, self.normal);
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let offset = hit.position - self.center;
+        let u = offset.mag() / self.radius;
+        let v = 0.5 + (offset * bitangent).atan2(offset * tangent) * INV_TAU;
+        Some(hit.with_uv((u, v)))
+    }
+
+    fn shadow_hit(&self, ray: &math::Ray, light_dist: f64) -> bool {
+        self.intersect_disk(ray, light_dist).is_some()
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        // The extent along each axis shrinks the more the disk faces along it.
+        let n = self.normal;
+        let extent = Vec3::new(
+            (1.0 - n.x * n.x).max(0.0).sqrt(),
+            (1.0 - n.y * n.y).max(0.0).sqrt(),
+            (1.0 - n.z * n.z).max(0.0).sqrt(),
+        ) * self.radius;
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(std::f64::consts::PI * self.radius * self.radius)
+    }
+
+    fn sample_point(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let radius = self.radius * u.0.sqrt();
+        let phi = std::f64::consts::TAU * u.1;
+        Some((self.center + tangent * (radius * phi.cos()) + bitangent * (radius * phi.sin()), self.normal))
+    }
+}
+
+/// Möller–Trumbore ray/triangle test, returns the distance and the barycentric weights of `b` and `c`.
+fn intersect_triangle(ray: &math::Ray, [a, b, c]: [Vec3; 3], max_distance: f64) -> Option<(f64, f64, f64)> {
+    let edge_1 = b - a;
+    let edge_2 = c - a;
+
+    let p = ray.direction.cross(edge_2);
+    let det = edge_1 * p;
+    if det.abs() < 1e-12 {
+        return None
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.origin - a;
+    let u = s * p * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None
+    }
+
+    let q = s.cross(edge_1);
+    let v = ray.direction * q * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None
+    }
+
+    let t = edge_2 * q * inv_det;
+    if t > EPSILON && t < max_distance {
+        return Some((t, u, v))
+    }
+    None
+}
+
+/// Intersects a triangle and fills in the interpolated normal and texture coordinate. Without texture coordinates
+/// the barycentric weights of `b` and `c` are used as uv.
+fn triangle_intersection(
+    ray: &math::Ray,
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    max_distance: f64,
+) -> Option<Intersection> {
+    let (t, u, v) = intersect_triangle(ray, vertices, max_distance)?;
+    let w = 1.0 - u - v;
+    let face_normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).normalize();
+    let normal = match normals {
+        // Vertices without a normal, like ones where only degenerate faces meet, shade with the face normal.
+        Some(normals) => {
+            let [na, nb, nc] = normals.map(|normal| if normal.mag2() > 0.0 { normal } else { face_normal });
+            Vec3::linear_combine(w, &na, u, &nb, v, &nc).normalize()
+        },
+        None => face_normal,
+    };
+    let uv = match uvs {
+        Some([a, b, c]) => (w * a.0 + u * b.0 + v * c.0, w * a.1 + u * b.1 + v * c.1),
+        None => (u, v),
+    };
+    Some(Intersection::new(ray, t, normal).with_uv(uv))
+}
+
+fn triangle_bounds([a, b, c]: [Vec3; 3]) -> Aabb {
+    Aabb::new(a, a).grow(b).grow(c)
+}
+
+fn triangle_area([a, b, c]: [Vec3; 3]) -> f64 {
+    (b - a).cross(c - a).mag() * 0.5
+}
+
+/// Uniform point on the triangle with the geometric normal, the side counter-clockwise vertices are seen from.
+fn triangle_sample([a, b, c]: [Vec3; 3], u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+    let normal = (b - a).cross(c - a);
+    if normal.mag2() == 0.0 {
+        return None
+    }
+    let (u, v) = sampling::uniform_triangle(u.0, u.1);
+    Some((Vec3::linear_combine(1.0 - u - v, &a, u, &b, v, &c), normal.normalize()))
+}
+
+/// A single triangle, counter-clockwise vertices face towards the viewer.
+pub struct Triangle {
+    pub material: Box<dyn Material>,
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+}
+
+impl Triangle {
+    pub fn new(material: Box<dyn Material>, a: Vec3, b: Vec3, c: Vec3) -> Self {
+        Self { material, vertices: [a, b, c], normals: None }
+    }
+
+    /// Interpolates the given per-vertex normals across the triangle for smooth shading.
+    pub fn with_normals(mut self, a: Vec3, b: Vec3, c: Vec3) -> Self {
+        self.normals = Some([a, b, c].map(normalize_or_zero));
+        self
+    }
+}
+
+impl Surface for Triangle {
+    fn get_material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn intersect(&self, ray: &math::Ray, max_distance: f64) -> Option<Intersection> {
+        triangle_intersection(ray, self.vertices, self.normals, None, max_distance)
+    }
+
+    fn shadow_hit(&self, ray: &math::Ray, light_dist: f64) -> bool {
+        intersect_triangle(ray, self.vertices, light_dist).is_some()
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(triangle_bounds(self.vertices))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(triangle_area(self.vertices))
+    }
+
+    fn sample_point(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        triangle_sample(self.vertices, u)
+    }
+}
+
+/// Indexed triangle mesh whose triangles share one vertex buffer and one material.
+///
+/// The mesh is added to a scene through [`TriangleMesh::into_surfaces`], which turns every triangle into its own
+/// surface so the scene BVH can cull them individually.
+pub struct TriangleMesh {
+    pub material: Box<dyn Material>,
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl TriangleMesh {
+    /// Fails if a face refers to a vertex that isn't in `positions`.
+    pub fn new(material: Box<dyn Material>, positions: Vec<Vec3>, indices: Vec<[usize; 3]>) -> Result<Self, MeshError> {
+        if let Some((face, &index)) = indices.iter().enumerate()
+            .find_map(|(face, vertices)| vertices.iter().find(|&&i| i >= positions.len()).map(|index| (face, index)))
+        {
+            return Err(MeshError::IndexOutOfRange { face, index, vertices: positions.len() })
+        }
+        Ok(Self { material, positions, normals: None, uvs: None, indices })
+    }
+
+    /// Uses one normal per vertex, interpolated with the barycentric coordinates of the hit. Fails if there isn't
+    /// exactly one normal per position.
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Result<Self, MeshError> {
+        self.check_len("normals", normals.len())?;
+        self.normals = Some(normals.into_iter().map(normalize_or_zero).collect());
+        Ok(self)
+    }
+
+    /// Attaches one texture coordinate per vertex. Fails if there isn't exactly one per position.
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> Result<Self, MeshError> {
+        self.check_len("texture coordinates", uvs.len())?;
+        self.uvs = Some(uvs);
+        Ok(self)
+    }
+
+    /// Computes area weighted vertex normals from the faces sharing each vertex. Vertices without a face of any
+    /// size shade with the normal of the face they are hit on.
+    pub fn with_smooth_normals(self) -> Self {
+        let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); self.positions.len()];
+        for &[a, b, c] in &self.indices {
+            let face = (self.positions[b] - self.positions[a]).cross(self.positions[c] - self.positions[a]);
+            normals[a] += face;
+            normals[b] += face;
+            normals[c] += face;
+        }
+        Self { normals: Some(normals.into_iter().map(normalize_or_zero).collect()), ..self }
+    }
+
+    fn check_len(&self, attribute: &'static str, found: usize) -> Result<(), MeshError> {
+        if found != self.positions.len() {
+            return Err(MeshError::LengthMismatch { attribute, expected: self.positions.len(), found })
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn into_surfaces(self) -> Vec<Box<dyn Surface>> {
+        let mesh = Arc::new(self);
+        (0..mesh.len())
+            .map(|index| Box::new(MeshTriangle { mesh: mesh.clone(), index }) as Box<dyn Surface>)
+            .collect()
+    }
+
+    fn vertices(&self, index: usize) -> [Vec3; 3] {
+        self.indices[index].map(|i| self.positions[i])
+    }
+
+    fn vertex_normals(&self, index: usize) -> Option<[Vec3; 3]> {
+        self.normals.as_ref().map(|normals| self.indices[index].map(|i| normals[i]))
+    }
+
+    fn vertex_uvs(&self, index: usize) -> Option<[(f64, f64); 3]> {
+        self.uvs.as_ref().map(|uvs| self.indices[index].map(|i| uvs[i]))
+    }
+}
+
+/// Why a [`TriangleMesh`] can't be built from its buffers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshError {
+    /// Face number `face` refers to vertex `index`, but there are only `vertices` of them.
+    IndexOutOfRange { face: usize, index: usize, vertices: usize },
+    /// There are `found` per-vertex `attribute` values, but `expected` positions.
+    LengthMismatch { attribute: &'static str, expected: usize, found: usize },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::IndexOutOfRange { face, index, vertices } => {
+                write!(f, "face {} refers to vertex {}, but there are only {} vertices", face, index, vertices)
+            },
+            MeshError::LengthMismatch { attribute, expected, found } => {
+                write!(f, "{} {} for {} positions", found, attribute, expected)
+            },
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+/// Unit length normal, zero vectors stay zero so they can be told apart instead of turning into NaN.
+fn normalize_or_zero(normal: Vec3) -> Vec3 {
+    if normal.mag2() > 0.0 { normal.normalize() } else { normal }
+}
+
+/// One triangle of a [`TriangleMesh`], referencing the shared vertex buffer.
+pub struct MeshTriangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+}
+
+impl Surface for MeshTriangle {
+    fn get_material(&self) -> &dyn Material {
+        self.mesh.material.as_ref()
+    }
+
+    fn intersect(&self, ray: &math::Ray, max_distance: f64) -> Option<Intersection> {
+        let mesh = &self.mesh;
+        triangle_intersection(ray, mesh.vertices(self.index), mesh.vertex_normals(self.index), mesh.vertex_uvs(self.index), max_distance)
+    }
+
+    fn shadow_hit(&self, ray: &math::Ray, light_dist: f64) -> bool {
+        intersect_triangle(ray, self.mesh.vertices(self.index), light_dist).is_some()
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(triangle_bounds(self.mesh.vertices(self.index)))
+    }
+
+    fn area(&self) -> Option<f64> {
+        Some(triangle_area(self.mesh.vertices(self.index)))
+    }
+
+    fn sample_point(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        triangle_sample(self.mesh.vertices(self.index), u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{math::Ray, materials::AlbedoMaterial};
+
+    fn material() -> Box<dyn Material> {
+        Box::new(AlbedoMaterial::new(0.into()))
+    }
+
+    /// Right triangle in the z = 0 plane facing +z.
+    fn triangle() -> Triangle {
+        Triangle::new(material(), Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0))
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn triangle_hit_and_miss() {
+        let triangle = triangle();
+        let hit = triangle.intersect(&Ray::new(Vec3::new(0.5, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0)), f64::INFINITY).expect("hit");
+        assert_close(hit.t, 3.0);
+        assert_close(hit.normal.z, 1.0);
+
+        // Outside the hypotenuse, beside the plane and beyond the maximum distance.
+        assert!(triangle.intersect(&Ray::new(Vec3::new(1.5, 1.5, 3.0), Vec3::new(0.0, 0.0, -1.0)), f64::INFINITY).is_none());
+        assert!(triangle.intersect(&Ray::new(Vec3::new(0.5, 0.5, 3.0), Vec3::new(1.0, 0.0, 0.0)), f64::INFINITY).is_none());
+        assert!(triangle.intersect(&Ray::new(Vec3::new(0.5, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0)), 2.0).is_none());
+        assert!(!triangle.shadow_hit(&Ray::new(Vec3::new(0.5, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0)), 2.0));
+    }
+
+    #[test]
+    fn triangle_barycentrics_and_uvs() {
+        let ray = Ray::new(Vec3::new(0.5, 1.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = triangle().intersect(&ray, f64::INFINITY).expect("hit");
+        let (u, v) = hit.uv.expect("barycentric uv");
+        assert_close(u, 0.25);
+        assert_close(v, 0.5);
+
+        let positions = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0)];
+        let mesh = TriangleMesh::new(material(), positions, vec![[0, 1, 2]]).unwrap()
+            .with_uvs(vec![(0.0, 0.0), (1.0, 0.0), (0.0, 4.0)]).unwrap();
+        let hit = mesh.into_surfaces()[0].intersect(&ray, f64::INFINITY).expect("hit");
+        let (u, v) = hit.uv.expect("uv");
+        assert_close(u, 0.25);
+        assert_close(v, 2.0);
+    }
+
+    #[test]
+    fn triangle_back_face() {
+        // Triangles are two sided, from behind the hit keeps the normal of the front side.
+        let triangle = triangle();
+        let ray = Ray::new(Vec3::new(0.5, 0.5, -3.0), Vec3::new(0.0, 0.0, 1.0));
+        let hit = triangle.intersect(&ray, f64::INFINITY).expect("hit from behind");
+        assert_close(hit.t, 3.0);
+        assert_close(hit.normal.z, 1.0);
+        assert!(triangle.shadow_hit(&ray, 5.0));
+
+        let (point, normal) = triangle.sample_point((0.3, 0.6)).expect("sample");
+        assert_close(point.z, 0.0);
+        assert_close(normal.z, 1.0);
+    }
+
+    #[test]
+    fn mesh_rejects_out_of_range_indices() {
+        let positions = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let error = TriangleMesh::new(material(), positions, vec![[0, 1, 2], [0, 2, 3]]).err();
+        assert_eq!(error, Some(MeshError::IndexOutOfRange { face: 1, index: 3, vertices: 3 }));
+    }
+
+    #[test]
+    fn mesh_rejects_attribute_length_mismatch() {
+        let positions = vec![Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        let mesh = || TriangleMesh::new(material(), positions.clone(), vec![[0, 1, 2]]).unwrap();
+        let error = mesh().with_normals(vec![Vec3::new(0.0, 0.0, 1.0); 2]).err();
+        assert_eq!(error, Some(MeshError::LengthMismatch { attribute: "normals", expected: 3, found: 2 }));
+        let error = mesh().with_uvs(vec![(0.0, 0.0); 4]).err();
+        assert_eq!(error, Some(MeshError::LengthMismatch { attribute: "texture coordinates", expected: 3, found: 4 }));
+    }
+
+    #[test]
+    fn smooth_normals_without_faces() {
+        // Vertex 3 belongs to no face, the two faces at vertex 4 have opposite winding and cancel out.
+        let positions = vec![
+            Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(5.0, 5.0, 5.0), Vec3::new(2.0, 0.0, 0.0),
+        ];
+        let mesh = TriangleMesh::new(material(), positions, vec![[0, 1, 2], [1, 4, 2], [1, 2, 4]]).unwrap().with_smooth_normals();
+        let normals = mesh.normals.as_ref().expect("normals");
+        assert_eq!(normals[3].mag2(), 0.0);
+        assert_eq!(normals[4].mag2(), 0.0);
+        assert!(normals.iter().all(|n| n.x.is_finite() && n.y.is_finite() && n.z.is_finite()));
+
+        let hit = mesh.into_surfaces()[1].intersect(&Ray::new(Vec3::new(1.2, 0.3, 2.0), Vec3::new(0.0, 0.0, -1.0)), f64::INFINITY).expect("hit");
+        assert_close(hit.normal.mag(), 1.0);
+        assert!(hit.normal.z > 0.0);
+    }
+}
@@ -0,0 +1,119 @@
+//! Building blocks of microfacet BSDFs. Directions are given in a local [`Frame`](crate::math::Frame) with the
+//! surface normal as z axis.
+
+use crate::{math::Vec3, color::Color, INV_PI};
+
+/// Isotropic GGX / Trowbridge-Reitz distribution of microfacet normals with height correlated Smith masking and
+/// shadowing.
+#[derive(Debug, Clone, Copy)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /// Perceptually linear roughness in `[0, 1]`, the distribution width is its square.
+    pub fn from_roughness(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self { alpha: roughness * roughness }
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    /// So narrow that it is treated as a perfect mirror, the lobe could not be sampled accurately anymore.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// Density of microfacet normals `m`.
+    pub fn d(&self, m: Vec3) -> f64 {
+        if m.z <= 0.0 {
+            return 0.0
+        }
+        let alpha2 = self.alpha * self.alpha;
+        let t = m.z * m.z * (alpha2 - 1.0) + 1.0;
+        alpha2 * INV_PI / (t * t)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f64::INFINITY
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) * 0.5
+    }
+
+    /// Fraction of the microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the microfacet normals visible from `w`, the density of [`Ggx::sample_visible`].
+    pub fn pdf_visible(&self, w: Vec3, m: Vec3) -> f64 {
+        if w.z == 0.0 {
+            return 0.0
+        }
+        self.g1(w) / w.z.abs() * self.d(m) * (w * m).abs()
+    }
+
+    /// Samples a microfacet normal visible from `w` (Heitz, "Sampling the GGX Distribution of Visible Normals").
+    pub fn sample_visible(&self, w: Vec3, u: (f64, f64)) -> Vec3 {
+        let mut stretched = Vec3::new(self.alpha * w.x, self.alpha * w.y, w.z).normalize();
+        if stretched.z < 0.0 {
+            stretched = -stretched;
+        }
+        let t1 = if stretched.z < 0.99999 {
+            Vec3::new(0.0, 0.0, 1.0).cross(stretched).normalize()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = stretched.cross(t1);
+
+        let radius = u.0.sqrt();
+        let phi = std::f64::consts::TAU * u.1;
+        let x = radius * phi.cos();
+        let h = (1.0 - x * x).max(0.0).sqrt();
+        let s = 0.5 * (1.0 + stretched.z);
+        let y = (1.0 - s) * h + s * radius * phi.sin();
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+        let m = Vec3::linear_combine(x, &t1, y, &t2, z, &stretched);
+        Vec3::new(self.alpha * m.x, self.alpha * m.y, m.z.max(1e-6)).normalize()
+    }
+}
+
+/// Fresnel reflectance of a conductor with the complex index of refraction `eta + i k` for one wavelength.
+pub fn fresnel_conductor(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i.clamp(0.0, 1.0) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+/// [`fresnel_conductor`] for the red, green and blue channels.
+pub fn fresnel_conductor_rgb(cos_i: f64, eta: Color, k: Color) -> Color {
+    Color::new(
+        fresnel_conductor(cos_i, eta.r, k.r),
+        fresnel_conductor(cos_i, eta.g, k.g),
+        fresnel_conductor(cos_i, eta.b, k.b),
+    )
+}
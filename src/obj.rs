@@ -0,0 +1,568 @@
+use std::{collections::HashMap, fmt, fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}, str::SplitWhitespace};
+
+use crate::{math::Vec3, color::Color, surface::Surface, meshes::{TriangleMesh, MeshError}, light::AreaLight, materials::{Material, DiffuseMaterial, SDMaterial, SDRMaterial, DielectricMaterial, PrincipledMaterial}};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, line: usize, message: String },
+    /// A group whose faces or per-vertex attributes don't fit its vertices, only possible for models that weren't
+    /// parsed from a file.
+    Mesh { group: String, source: MeshError },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Mesh { group, source } => write!(f, "group `{}`: {}", group, source),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+            ObjError::Mesh { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Loads an `.obj` file together with the `.mtl` libraries it references and turns it into scene surfaces.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Vec<Box<dyn Surface>>, ObjError> {
+    ObjModel::load(path)?.into_surfaces()
+}
+
+/// Surfaces of a model together with the area light its emissive groups form, see
+/// [`ObjModel::into_surfaces_and_light`].
+pub type LitSurfaces = (Vec<Box<dyn Surface>>, Option<AreaLight>);
+
+/// A material read from an `.mtl` file.
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// Diffuse color `Kd`.
+    pub diffuse: Color,
+    /// Specular color `Ks`.
+    pub specular: Color,
+    /// Specular exponent `Ns`.
+    pub exponent: f64,
+    /// Index of refraction `Ni`.
+    pub ior: f64,
+    /// Opacity `d`, or `1 - Tr`.
+    pub dissolve: f64,
+    /// Illumination model `illum`.
+    pub illum: u32,
+    /// Emitted color `Ke`.
+    pub emission: Color,
+    /// Roughness `Pr` of the PBR extension.
+    pub roughness: Option<f64>,
+    /// Metalness `Pm` of the PBR extension.
+    pub metallic: Option<f64>,
+    /// Sheen `Ps` of the PBR extension.
+    pub sheen: f64,
+    /// Clear coat thickness `Pc` and roughness `Pcr` of the PBR extension.
+    pub clearcoat: (f64, f64),
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            exponent: 10.0,
+            ior: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            emission: Color::new(0.0, 0.0, 0.0),
+            roughness: None,
+            metallic: None,
+            sheen: 0.0,
+            clearcoat: (0.0, 0.03),
+        }
+    }
+
+    /// Maps the MTL illumination model onto the closest material of this crate: `illum 0` and `1` are purely
+    /// diffuse, `2` adds the specular highlight, the refracting models `4`, `6`, `7` and `9` become glass with the
+    /// index of refraction `Ni` and the remaining ones add mirror reflection weighted by `Ks`.
+    ///
+    /// Materials using the PBR extension (`Pr` or `Pm`), partly transparent ones (`d` below one) and emissive ones
+    /// (`Ke`) become a [`PrincipledMaterial`] instead. It refracts with `Ni` when `illum` is one of the refracting
+    /// models and otherwise transmits `1 - d` of the light.
+    pub fn to_material(&self) -> Box<dyn Material> {
+        let pbr = self.roughness.is_some() || self.metallic.is_some();
+        let refracting = matches!(self.illum, 4 | 6 | 7 | 9);
+        if pbr || (self.dissolve < 1.0 && !refracting) || self.is_emissive() {
+            // The Phong exponent converted to a microfacet width, `alpha = sqrt(2 / (Ns + 2))`.
+            let roughness = self.roughness.unwrap_or_else(|| (2.0 / (self.exponent.max(0.0) + 2.0)).powf(0.25));
+            let transmission = if refracting { 1.0 } else { 1.0 - self.dissolve.clamp(0.0, 1.0) };
+            let mut material = PrincipledMaterial::new(self.diffuse)
+                .with_metallic(self.metallic.unwrap_or(0.0))
+                .with_roughness(roughness)
+                .with_sheen(self.sheen, 0.5)
+                .with_clearcoat(self.clearcoat.0, self.clearcoat.1)
+                .with_emission(self.emission);
+            if transmission > 0.0 {
+                material = material.with_transmission(transmission).with_ior(self.ior);
+            }
+            return Box::new(material)
+        }
+
+        let specular_coeff = self.specular.r.max(self.specular.g).max(self.specular.b);
+
+        match self.illum {
+            0 | 1 => Box::new(DiffuseMaterial::new(self.diffuse, 1.0)),
+            2 => Box::new(SDMaterial::new(self.diffuse, 1.0, specular_coeff, self.exponent)),
+            4 | 6 | 7 | 9 => Box::new(DielectricMaterial::new(self.ior)),
+            _ => Box::new(SDRMaterial::new(self.diffuse, 1.0, specular_coeff, self.exponent, specular_coeff)),
+        }
+    }
+
+    /// Whether the material gives off light, surfaces with it have to be sampled by an area light.
+    pub fn is_emissive(&self) -> bool {
+        !self.emission.is_black()
+    }
+}
+
+/// One triangulated group of an `.obj` file, faces with different materials end up in separate groups.
+#[derive(Debug, Clone)]
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Option<String>,
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f64, f64)>>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+    pub materials: HashMap<String, MtlMaterial>,
+}
+
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+struct GroupBuilder {
+    name: String,
+    material: Option<String>,
+    vertices: Vec<FaceVertex>,
+    lookup: HashMap<FaceVertex, usize>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl GroupBuilder {
+    fn vertex(&mut self, vertex: FaceVertex) -> usize {
+        *self.lookup.entry(vertex).or_insert_with(|| {
+            self.vertices.push(vertex);
+            self.vertices.len() - 1
+        })
+    }
+
+    fn build(self, positions: &[Vec3], uvs: &[(f64, f64)], normals: &[Vec3]) -> ObjGroup {
+        let has_uvs = self.vertices.iter().all(|v| v.1.is_some());
+        let has_normals = self.vertices.iter().all(|v| v.2.is_some());
+
+        ObjGroup {
+            name: self.name,
+            material: self.material,
+            positions: self.vertices.iter().map(|v| positions[v.0]).collect(),
+            uvs: has_uvs.then(|| self.vertices.iter().filter_map(|v| v.1.map(|i| uvs[i])).collect()),
+            normals: has_normals.then(|| self.vertices.iter().filter_map(|v| v.2.map(|i| normals[i])).collect()),
+            indices: self.indices,
+        }
+    }
+}
+
+impl ObjModel {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|source| ObjError::Io { path: path.to_path_buf(), source })?;
+        Self::parse(BufReader::new(file), path)
+    }
+
+    /// Parses an `.obj` file from a reader, `path` is used for error messages and to resolve `mtllib` statements.
+    pub fn parse<R: BufRead>(reader: R, path: &Path) -> Result<Self, ObjError> {
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut normals = Vec::new();
+        let mut materials = HashMap::new();
+
+        let mut builders: Vec<GroupBuilder> = Vec::new();
+        let mut lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+        let mut group = String::from("default");
+        let mut material: Option<String> = None;
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|source| ObjError::Io { path: path.to_path_buf(), source })?;
+            let error = |message: String| ObjError::Parse { path: path.to_path_buf(), line: number + 1, message };
+
+            let line = line.split('#').next().unwrap_or("");
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else { continue };
+
+            match keyword {
+                "v" => positions.push(parse_vec3(&mut tokens).map_err(error)?),
+                "vn" => normals.push(parse_vec3(&mut tokens).map_err(error)?),
+                "vt" => {
+                    let u = parse_f64(tokens.next(), "texture coordinate").map_err(error)?;
+                    let v = tokens.next().map_or(Ok(0.0), |v| parse_f64(Some(v), "texture coordinate")).map_err(error)?;
+                    uvs.push((u, v));
+                },
+                "f" => {
+                    let face = tokens
+                        .map(|token| parse_face_vertex(token, positions.len(), uvs.len(), normals.len()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(error)?;
+                    if face.len() < 3 {
+                        return Err(error(format!("face needs at least 3 vertices, found {}", face.len())))
+                    }
+
+                    let key = (group.clone(), material.clone());
+                    let builder = *lookup.entry(key).or_insert_with(|| {
+                        builders.push(GroupBuilder {
+                            name: group.clone(),
+                            material: material.clone(),
+                            vertices: Vec::new(),
+                            lookup: HashMap::new(),
+                            indices: Vec::new(),
+                        });
+                        builders.len() - 1
+                    });
+                    let builder = &mut builders[builder];
+
+                    // Polygons are triangulated as a fan around their first vertex.
+                    let first = builder.vertex(face[0]);
+                    for pair in face[1..].windows(2) {
+                        let b = builder.vertex(pair[0]);
+                        let c = builder.vertex(pair[1]);
+                        builder.indices.push([first, b, c]);
+                    }
+                },
+                "g" | "o" => group = rest_of_line(tokens).unwrap_or_else(|| String::from("default")),
+                "usemtl" => material = rest_of_line(tokens),
+                "mtllib" => {
+                    for library in tokens {
+                        materials.extend(load_mtl(base_dir.join(library))?);
+                    }
+                },
+                // Smoothing groups, lines, points and free-form geometry are not supported.
+                _ => {},
+            }
+        }
+
+        let groups = builders.into_iter()
+            .filter(|builder| !builder.indices.is_empty())
+            .map(|builder| builder.build(&positions, &uvs, &normals))
+            .collect();
+
+        Ok(Self { groups, materials })
+    }
+
+    /// Builds one triangle mesh per group, using the group's material or a light grey diffuse material if it has none.
+    pub fn into_surfaces(self) -> Result<Vec<Box<dyn Surface>>, ObjError> {
+        let (mut surfaces, light) = self.into_surfaces_and_light()?;
+        surfaces.extend(light.map(|light| light.surfaces()).unwrap_or_default());
+        Ok(surfaces)
+    }
+
+    /// Like [`ObjModel::into_surfaces`], but the groups with an emissive material go into an [`AreaLight`] that
+    /// lights the scene with them. Its [`AreaLight::surfaces`] still have to be added to the scene.
+    pub fn into_surfaces_and_light(self) -> Result<LitSurfaces, ObjError> {
+        let mut surfaces = Vec::new();
+        let mut emitters = Vec::new();
+        for group in self.groups {
+            let default = MtlMaterial::new("default");
+            let mtl = group.material.as_ref().and_then(|name| self.materials.get(name)).unwrap_or(&default);
+
+            let mesh_error = |source| ObjError::Mesh { group: group.name.clone(), source };
+            let mut mesh = TriangleMesh::new(mtl.to_material(), group.positions, group.indices).map_err(mesh_error)?;
+            if let Some(normals) = group.normals {
+                mesh = mesh.with_normals(normals).map_err(mesh_error)?;
+            }
+            if let Some(uvs) = group.uvs {
+                mesh = mesh.with_uvs(uvs).map_err(mesh_error)?;
+            }
+            if mtl.is_emissive() {
+                emitters.extend(mesh.into_surfaces());
+            } else {
+                surfaces.extend(mesh.into_surfaces());
+            }
+        }
+        Ok((surfaces, (!emitters.is_empty()).then(|| AreaLight::new(emitters))))
+    }
+}
+
+/// Reads all materials of an `.mtl` library.
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|source| ObjError::Io { path: path.to_path_buf(), source })?;
+
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlMaterial> = None;
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|source| ObjError::Io { path: path.to_path_buf(), source })?;
+        let error = |message: String| ObjError::Parse { path: path.to_path_buf(), line: number + 1, message };
+
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else { continue };
+
+        if keyword == "newmtl" {
+            let name = rest_of_line(tokens).ok_or_else(|| error(String::from("missing material name")))?;
+            if let Some(material) = current.replace(MtlMaterial::new(&name)) {
+                materials.insert(material.name.clone(), material);
+            }
+            continue
+        }
+
+        let Some(material) = current.as_mut() else {
+            return Err(error(format!("`{}` before the first `newmtl`", keyword)))
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_color(&mut tokens).map_err(error)?,
+            "Ks" => material.specular = parse_color(&mut tokens).map_err(error)?,
+            "Ns" => material.exponent = parse_f64(tokens.next(), "specular exponent").map_err(error)?,
+            "Ni" => material.ior = parse_f64(tokens.next(), "index of refraction").map_err(error)?,
+            "d" => material.dissolve = parse_f64(tokens.next(), "dissolve").map_err(error)?,
+            "Tr" => material.dissolve = 1.0 - parse_f64(tokens.next(), "transparency").map_err(error)?,
+            "Ke" => material.emission = parse_color(&mut tokens).map_err(error)?,
+            "Pr" => material.roughness = Some(parse_f64(tokens.next(), "roughness").map_err(error)?),
+            "Pm" => material.metallic = Some(parse_f64(tokens.next(), "metallic").map_err(error)?),
+            "Ps" => material.sheen = parse_f64(tokens.next(), "sheen").map_err(error)?,
+            "Pc" => material.clearcoat.0 = parse_f64(tokens.next(), "clear coat thickness").map_err(error)?,
+            "Pcr" => material.clearcoat.1 = parse_f64(tokens.next(), "clear coat roughness").map_err(error)?,
+            "illum" => {
+                let value = tokens.next().unwrap_or("");
+                material.illum = value.parse().map_err(|_| error(format!("invalid illumination model `{}`", value)))?;
+            },
+            // Texture maps and the remaining statements have no counterpart in the renderer.
+            _ => {},
+        }
+    }
+
+    if let Some(material) = current {
+        materials.insert(material.name.clone(), material);
+    }
+    Ok(materials)
+}
+
+fn rest_of_line(tokens: SplitWhitespace) -> Option<String> {
+    let rest = tokens.collect::<Vec<_>>().join(" ");
+    (!rest.is_empty()).then_some(rest)
+}
+
+fn parse_f64(token: Option<&str>, what: &str) -> Result<f64, String> {
+    let token = token.ok_or_else(|| format!("missing {}", what))?;
+    token.parse().map_err(|_| format!("invalid {} `{}`", what, token))
+}
+
+fn parse_vec3(tokens: &mut SplitWhitespace) -> Result<Vec3, String> {
+    Ok(Vec3::new(
+        parse_f64(tokens.next(), "coordinate")?,
+        parse_f64(tokens.next(), "coordinate")?,
+        parse_f64(tokens.next(), "coordinate")?,
+    ))
+}
+
+fn parse_color(tokens: &mut SplitWhitespace) -> Result<Color, String> {
+    let r = parse_f64(tokens.next(), "color component")?;
+    // A single value is a grey level.
+    match tokens.next() {
+        Some(g) => Ok(Color::new(r, parse_f64(Some(g), "color component")?, parse_f64(tokens.next(), "color component")?)),
+        None => Ok(Color::new(r, r, r)),
+    }
+}
+
+/// Resolves a 1-based or negative (relative to the end) index into a 0-based one.
+fn parse_index(token: &str, len: usize, what: &str) -> Result<usize, String> {
+    let index: i64 = token.parse().map_err(|_| format!("invalid {} index `{}`", what, token))?;
+    let resolved = match index {
+        i if i > 0 => i - 1,
+        i if i < 0 => len as i64 + i,
+        _ => return Err(format!("{} index must not be 0", what)),
+    };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(format!("{} index {} out of range, {} defined so far", what, index, len))
+    }
+    Ok(resolved as usize)
+}
+
+/// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+fn parse_face_vertex(token: &str, positions: usize, uvs: usize, normals: usize) -> Result<FaceVertex, String> {
+    let mut parts = token.split('/');
+    let position = parse_index(parts.next().unwrap_or(""), positions, "vertex")?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(uv) => Some(parse_index(uv, uvs, "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(normal) => Some(parse_index(normal, normals, "normal")?),
+    };
+    Ok((position, uv, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::INV_PI;
+
+    fn parse(text: &str) -> Result<ObjModel, ObjError> {
+        ObjModel::parse(text.as_bytes(), Path::new("model.obj"))
+    }
+
+    fn parse_error(text: &str) -> String {
+        match parse(text) {
+            Ok(_) => panic!("parsed invalid model:\n{}", text),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    /// Writes `text` to a file of its own in the temporary directory.
+    fn temp_file(name: &str, text: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-obj-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    /// Material `m` from a library file `name`.
+    fn mtl(name: &str, text: &str) -> MtlMaterial {
+        load_mtl(temp_file(name, text)).unwrap().remove("m").expect("material `m`")
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn fan_triangulation() {
+        let model = parse("v 0 0 0\nv 1 0 0\nv 2 1 0\nv 1 2 0\nv 0 1 0\nf 1 2 3 4 5\n").unwrap();
+        assert_eq!(model.groups.len(), 1);
+        assert_eq!(model.groups[0].indices, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+        assert_eq!(model.groups[0].positions.len(), 5);
+    }
+
+    #[test]
+    fn negative_indices() {
+        // Relative indices count back from the vertices defined so far, not from the end of the file.
+        let model = parse(&format!("{}f -4 -3 -2\nv 5 5 5\nf 1 -3 -2\n", SQUARE)).unwrap();
+        let group = &model.groups[0];
+        assert_eq!(group.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_close(group.positions[3].y, 1.0);
+        assert_close(group.positions[3].x, 0.0);
+    }
+
+    #[test]
+    fn face_vertex_forms() {
+        let attributes = "vt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 1\nvn 0 0 -1\n";
+
+        let model = parse(&format!("{}{}f 1/1/1 2/2/1 3/3/1\nf 1/1/1 3/3/1 4/1/2\n", SQUARE, attributes)).unwrap();
+        let group = &model.groups[0];
+        // Corners with the same position, uv and normal are shared, vertex 4 is split from vertex 1 by its normal.
+        assert_eq!(group.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        let uvs = group.uvs.as_ref().expect("uvs");
+        assert_eq!(uvs[1], (1.0, 0.0));
+        let normals = group.normals.as_ref().expect("normals");
+        assert_close(normals[3].z, -1.0);
+
+        let model = parse(&format!("{}{}f 1//1 2//1 3//2\n", SQUARE, attributes)).unwrap();
+        assert!(model.groups[0].uvs.is_none());
+        assert_close(model.groups[0].normals.as_ref().expect("normals")[2].z, -1.0);
+
+        let model = parse(&format!("{}{}f 1/1 2/2 3/3\n", SQUARE, attributes)).unwrap();
+        assert!(model.groups[0].normals.is_none());
+        assert_eq!(model.groups[0].uvs.as_ref().expect("uvs")[2], (1.0, 1.0));
+
+        // Corners without a normal leave the whole group without normals.
+        let model = parse(&format!("{}{}f 1//1 2//1 3//1\nf 1 3 4\n", SQUARE, attributes)).unwrap();
+        assert!(model.groups[0].normals.is_none());
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        assert_eq!(parse_error("v 0 0 0\nv 1 x 0\n"), "model.obj:2: invalid coordinate `x`");
+        assert_eq!(parse_error(&format!("{}# comment\nf 1 2\n", SQUARE)), "model.obj:6: face needs at least 3 vertices, found 2");
+        assert_eq!(parse_error(&format!("{}f 1 2 0\n", SQUARE)), "model.obj:5: vertex index must not be 0");
+        assert_eq!(parse_error(&format!("f 1 2 3\n{}", SQUARE)), "model.obj:1: vertex index 1 out of range, 0 defined so far");
+        assert_eq!(parse_error(&format!("{}f 1 2 -5\n", SQUARE)), "model.obj:5: vertex index -5 out of range, 4 defined so far");
+        assert_eq!(parse_error(&format!("{}vt 0 0\nf 1/1 2/2 3/1\n", SQUARE)), "model.obj:6: texture coordinate index 2 out of range, 1 defined so far");
+
+        let path = temp_file("errors.mtl", "newmtl m\nKd 1 1 1\nNs high\n");
+        let error = load_mtl(&path).expect_err("loaded invalid material library").to_string();
+        assert_eq!(error, format!("{}:3: invalid specular exponent `high`", path.display()));
+        let path = temp_file("orphan.mtl", "# no material yet\nKd 1 1 1\n");
+        let error = load_mtl(&path).expect_err("loaded invalid material library").to_string();
+        assert_eq!(error, format!("{}:2: `Kd` before the first `newmtl`", path.display()));
+    }
+
+    #[test]
+    fn mtl_to_material() {
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let through = Vec3::new(0.0, 0.0, -1.0);
+
+        let diffuse = mtl("diffuse.mtl", "newmtl m\nKd 0.5 0.25 1\nillum 1\n").to_material();
+        assert_close(diffuse.eval(normal, normal, normal).g, 0.25 * INV_PI);
+
+        // Opaque materials don't transmit, partly dissolved ones transmit `1 - d` through rough glass.
+        let opaque = mtl("opaque.mtl", "newmtl m\nKd 1 1 1\nNi 1.5\nPr 0.5\n").to_material();
+        assert_eq!(opaque.eval(normal, through, normal).g, 0.0);
+        let dissolved = mtl("dissolved.mtl", "newmtl m\nKd 1 1 1\nNi 1.5\nPr 0.5\nd 0.25\n").to_material();
+        let half = mtl("transparent.mtl", "newmtl m\nKd 1 1 1\nNi 1.5\nPr 0.5\nTr 0.5\n").to_material();
+        let (dissolved, half) = (dissolved.eval(normal, through, normal).g, half.eval(normal, through, normal).g);
+        assert!(dissolved > 0.0);
+        assert_close(dissolved / half, 0.75 / 0.5);
+
+        // Refracting illumination models are smooth glass that passes light at normal incidence straight through.
+        let glass = mtl("glass.mtl", "newmtl m\nNi 1.5\nillum 7\n").to_material();
+        let sample = glass.sample(normal, normal, 0.99, (0.5, 0.5)).expect("refraction");
+        assert!(sample.specular);
+        assert_close(sample.direction.z, -1.0);
+
+        let emissive = mtl("emissive.mtl", "newmtl m\nKe 2 2 2\n");
+        assert!(emissive.is_emissive());
+        assert_close(emissive.to_material().emitted(normal, normal).r, 2.0);
+        assert!(!mtl("black.mtl", "newmtl m\nKe 0 0 0\n").is_emissive());
+    }
+
+    #[test]
+    fn emissive_groups_become_lights() {
+        let mut model = parse(&format!("{}usemtl lamp\nf 1 2 3\nusemtl wall\nf 1 3 4\n", SQUARE)).unwrap();
+        let mut lamp = MtlMaterial::new("lamp");
+        lamp.emission = Color::new(4.0, 4.0, 4.0);
+        model.materials.insert(String::from("lamp"), lamp);
+        model.materials.insert(String::from("wall"), MtlMaterial::new("wall"));
+
+        let (surfaces, light) = model.clone().into_surfaces_and_light().unwrap();
+        assert_eq!(surfaces.len(), 1);
+        let light = light.expect("light from the `Ke` group");
+        assert_close(light.area(), 0.5);
+        assert_eq!(light.surfaces().len(), 1);
+
+        // Without asking for the light the emitters stay part of the surfaces.
+        assert_eq!(model.into_surfaces().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn mismatched_attributes_are_errors() {
+        let mut model = parse(&format!("{}f 1 2 3\n", SQUARE)).unwrap();
+        model.groups[0].normals = Some(vec![Vec3::new(0.0, 0.0, 1.0); 2]);
+        assert!(matches!(
+            model.into_surfaces(),
+            Err(ObjError::Mesh { source: MeshError::LengthMismatch { expected: 3, found: 2, .. }, .. })
+        ));
+    }
+}
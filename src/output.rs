@@ -0,0 +1,219 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+use exr::prelude::f16;
+use image::{ImageError, ImageFormat, error::{EncodingError, ImageFormatHint}};
+
+use crate::{camera::Screen, tonemap::ExportSettings};
+
+/// File format of an exported image.
+///
+/// Integer formats go through the [`ExportSettings`] display transform, floating point formats store the linear
+/// radiance of the screen unclamped and as it is. With [`ExportSettings::alpha`] PNG is written with straight alpha
+/// and OpenEXR with premultiplied alpha as its convention is, the other formats have no alpha channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 8 bits per channel in any format the `image` crate can write.
+    Ldr(ImageFormat),
+    /// PNG with 16 bits per channel.
+    Png16,
+    /// OpenEXR with half float channels.
+    ExrHalf,
+    /// OpenEXR with 32 bit float channels.
+    ExrFloat,
+    /// Radiance RGBE, a shared 8 bit exponent for the three channels.
+    Hdr,
+    /// Portable float map, uncompressed 32 bit floats.
+    Pfm,
+}
+
+impl OutputFormat {
+    /// Picks the format from the file extension, `.exr` is written with full floats.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => ImageFormat::from_extension(extension).map(Self::from),
+        }
+    }
+
+    /// Whether the format stores linear radiance instead of display values.
+    pub fn is_float(&self) -> bool {
+        matches!(self, OutputFormat::ExrHalf | OutputFormat::ExrFloat | OutputFormat::Hdr | OutputFormat::Pfm)
+    }
+
+    pub(crate) fn save(&self, screen: &Screen, path: &Path, settings: &ExportSettings) -> Result<(), ImageError> {
+        match *self {
+            OutputFormat::Ldr(format) if settings.alpha && supports_alpha(format) => {
+                screen.to_rgba8(settings).save_with_format(path, format)
+            },
+            OutputFormat::Ldr(format) => screen.to_rgb8(settings).save_with_format(path, format),
+            OutputFormat::Png16 if settings.alpha => screen.to_rgba16(settings).save_with_format(path, ImageFormat::Png),
+            OutputFormat::Png16 => screen.to_rgb16(settings).save_with_format(path, ImageFormat::Png),
+            OutputFormat::ExrHalf if settings.alpha => write_exr_rgba(screen, path, f16::from_f32),
+            OutputFormat::ExrHalf => write_exr(screen, path, f16::from_f32),
+            OutputFormat::ExrFloat if settings.alpha => write_exr_rgba(screen, path, |c| c),
+            OutputFormat::ExrFloat => write_exr(screen, path, |c| c),
+            OutputFormat::Hdr => write_hdr(screen, path),
+            OutputFormat::Pfm => write_pfm(screen, path),
+        }
+    }
+}
+
+impl From<ImageFormat> for OutputFormat {
+    fn from(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::OpenExr => OutputFormat::ExrFloat,
+            ImageFormat::Hdr => OutputFormat::Hdr,
+            format => OutputFormat::Ldr(format),
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png16" => Ok(OutputFormat::Png16),
+            "exr-half" => Ok(OutputFormat::ExrHalf),
+            "pfm" => Ok(OutputFormat::Pfm),
+            _ => ImageFormat::from_extension(s).map(Self::from).ok_or_else(|| format!("unknown format `{}`", s)),
+        }
+    }
+}
+
+/// Formats the `image` crate can write with an alpha channel.
+fn supports_alpha(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Tga | ImageFormat::Ico)
+}
+
+fn linear_rgb(screen: &Screen, x: usize, y: usize) -> [f32; 3] {
+    let color = screen.color(x, y);
+    [color.r as f32, color.g as f32, color.b as f32]
+}
+
+fn write_exr<T, F>(screen: &Screen, path: &Path, convert: F) -> Result<(), ImageError>
+where
+    T: exr::image::IntoSample,
+    F: Fn(f32) -> T + Sync,
+{
+    exr::prelude::write_rgb_file(path, screen.width, screen.height, |x, y| {
+        let [r, g, b] = linear_rgb(screen, x, y);
+        (convert(r), convert(g), convert(b))
+    }).map_err(exr_error)
+}
+
+/// The color of the screen is already premultiplied, transparent samples add nothing to it.
+fn write_exr_rgba<T, F>(screen: &Screen, path: &Path, convert: F) -> Result<(), ImageError>
+where
+    T: exr::image::IntoSample,
+    F: Fn(f32) -> T + Sync,
+{
+    exr::prelude::write_rgba_file(path, screen.width, screen.height, |x, y| {
+        let [r, g, b] = linear_rgb(screen, x, y);
+        (convert(r), convert(g), convert(b), convert(screen.alpha(x, y) as f32))
+    }).map_err(exr_error)
+}
+
+fn exr_error(error: exr::error::Error) -> ImageError {
+    match error {
+        exr::error::Error::Io(error) => ImageError::IoError(error),
+        error => ImageError::Encoding(EncodingError::new(ImageFormatHint::Exact(ImageFormat::OpenExr), error)),
+    }
+}
+
+fn write_hdr(screen: &Screen, path: &Path) -> Result<(), ImageError> {
+    let mut data = Vec::with_capacity(screen.width * screen.height);
+    for y in 0..screen.height {
+        for x in 0..screen.width {
+            // RGBE can't store negative values, which the negative lobes of some filters can produce.
+            data.push(image::Rgb(linear_rgb(screen, x, y).map(|c| c.max(0.0))));
+        }
+    }
+    let writer = BufWriter::new(File::create(path)?);
+    image::codecs::hdr::HdrEncoder::new(writer).encode(&data, screen.width, screen.height)
+}
+
+fn write_pfm(screen: &Screen, path: &Path) -> Result<(), ImageError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    // A negative scale marks little endian data, the rows are stored from the bottom up.
+    write!(writer, "PF\n{} {}\n-1.0\n", screen.width, screen.height)?;
+    for y in (0..screen.height).rev() {
+        for x in 0..screen.width {
+            for channel in linear_rgb(screen, x, y) {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::color::Color;
+
+    /// 3x2 screen with distinct values per pixel and channel, including radiance above one and a negative lobe.
+    fn screen() -> Screen {
+        let mut screen = Screen::new(3, 2, 1.0, 1.0);
+        for (x, y) in screen.clone() {
+            let value = (x + 3 * y) as f64;
+            let alpha = if x == 1 { 0.5 } else { 1.0 };
+            screen.pixels[x][y].add_sample(Color::new(value * 0.75, value + 2.5, -0.125 * value), alpha, 1.0);
+        }
+        screen
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-output-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let screen = screen();
+        let path = temp_path("round_trip.pfm");
+        OutputFormat::Pfm.save(&screen, &path, &ExportSettings::default()).unwrap();
+
+        let data = fs::read(&path).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        let floats: Vec<f32> = data[header.len()..].chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+        assert_eq!(floats.len(), 3 * 2 * 3);
+        for (i, pixel) in floats.chunks_exact(3).enumerate() {
+            // Rows are stored from the bottom up.
+            let (x, y) = (i % 3, 1 - i / 3);
+            assert_eq!(pixel, linear_rgb(&screen, x, y), "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let screen = screen();
+        let path = temp_path("round_trip.exr");
+        OutputFormat::ExrFloat.save(&screen, &path, &ExportSettings::default()).unwrap();
+        let image = image::open(&path).unwrap().into_rgba32f();
+        assert_eq!(image.dimensions(), (3, 2));
+        for (x, y) in screen.clone() {
+            let [r, g, b, a] = image.get_pixel(x as u32, y as u32).0;
+            assert_eq!([r, g, b], linear_rgb(&screen, x, y), "pixel ({}, {})", x, y);
+            assert_eq!(a, 1.0);
+        }
+
+        // With alpha the color stays premultiplied, half floats round to 11 significant bits.
+        let path = temp_path("round_trip_alpha.exr");
+        OutputFormat::ExrHalf.save(&screen, &path, &ExportSettings::default().with_alpha(true)).unwrap();
+        let image = image::open(&path).unwrap().into_rgba32f();
+        for (x, y) in screen.clone() {
+            let pixel = image.get_pixel(x as u32, y as u32).0;
+            let expected = linear_rgb(&screen, x, y);
+            for (read, written) in pixel.iter().zip(expected.iter().chain([screen.alpha(x, y) as f32].iter())) {
+                assert!((read - written).abs() <= written.abs() / 1024.0, "pixel ({}, {}): {:?} != {:?}", x, y, pixel, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use std::sync::OnceLock;
+
+use crate::{math::Vec3, INV_PI};
+
+/// Small PCG32 random number generator. Renders seed one per pixel, so the result doesn't depend on which
+/// thread traced the pixel.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    increment: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0, increment: (seed << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(0x853c_49e6_748f_ea9b ^ seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// Seeds a generator for the given pixel.
+    pub fn for_pixel(x: usize, y: usize) -> Self {
+        Self::new(hash(((y as u64) << 32) | x as u64))
+    }
+
+    /// Seeds a generator for a point in the scene, for shading that has no generator of its own.
+    pub fn for_point(point: Vec3) -> Self {
+        Self::new(hash(point.x.to_bits() ^ hash(point.y.to_bits() ^ hash(point.z.to_bits()))))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(self.increment);
+        let shifted = (((old >> 18) ^ old) >> 27) as u32;
+        shifted.rotate_right((old >> 59) as u32)
+    }
+
+    /// Uniform number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        self.next_u32() as f64 * (1.0 / 4_294_967_296.0)
+    }
+}
+
+fn hash(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// How the samples of one pixel are distributed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplePattern {
+    /// Cell centers of a regular grid.
+    Regular,
+    /// One random point in every cell of a regular grid.
+    Jittered,
+    /// Halton sequence in bases 2 and 3, randomly shifted per pixel.
+    Halton,
+    /// The first two dimensions of the Sobol sequence, randomly scrambled per pixel.
+    Sobol,
+}
+
+impl SamplePattern {
+    /// Generates `count` points in the unit square.
+    pub fn generate(&self, count: usize, rng: &mut Rng) -> Vec<(f64, f64)> {
+        match self {
+            SamplePattern::Regular | SamplePattern::Jittered => {
+                let (columns, rows) = grid_size(count);
+                let mut points: Vec<_> = (0..columns * rows).map(|i| {
+                    let (dx, dy) = match self {
+                        SamplePattern::Jittered => (rng.next_f64(), rng.next_f64()),
+                        _ => (0.5, 0.5),
+                    };
+                    (((i % columns) as f64 + dx) / columns as f64, ((i / columns) as f64 + dy) / rows as f64)
+                }).collect();
+                points.extend((points.len()..count).map(|_| (rng.next_f64(), rng.next_f64())));
+                points
+            },
+            SamplePattern::Halton => {
+                let (shift_x, shift_y) = (rng.next_f64(), rng.next_f64());
+                (0..count).map(|i| {
+                    ((radical_inverse(2, i as u64 + 1) + shift_x).fract(), (radical_inverse(3, i as u64 + 1) + shift_y).fract())
+                }).collect()
+            },
+            SamplePattern::Sobol => {
+                let (scramble_x, scramble_y) = (rng.next_u32(), rng.next_u32());
+                (0..count).map(|i| {
+                    let i = i as u32;
+                    (to_unit(i.reverse_bits() ^ scramble_x), to_unit(sobol_second_dimension(i) ^ scramble_y))
+                }).collect()
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for SamplePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regular" => Ok(SamplePattern::Regular),
+            "jittered" => Ok(SamplePattern::Jittered),
+            "halton" => Ok(SamplePattern::Halton),
+            "sobol" => Ok(SamplePattern::Sobol),
+            _ => Err(format!("unknown sample pattern `{}`, expected regular, jittered, halton or sobol", s)),
+        }
+    }
+}
+
+/// The most square grid with exactly `count` cells. Counts that only factor into long strips get the largest
+/// square grid that fits instead, the caller fills the remaining samples randomly.
+fn grid_size(count: usize) -> (usize, usize) {
+    let side = (count as f64).sqrt() as usize;
+    match (1..=side).rev().find(|rows| count % rows == 0) {
+        Some(rows) if count / rows <= 2 * rows => (count / rows, rows),
+        _ => (side, side),
+    }
+}
+
+fn radical_inverse(base: u64, mut i: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut factor = inv_base;
+    let mut result = 0.0;
+    while i > 0 {
+        result += (i % base) as f64 * factor;
+        i /= base;
+        factor *= inv_base;
+    }
+    result
+}
+
+fn sobol_second_dimension(mut i: u32) -> u32 {
+    let mut v = 1u32 << 31;
+    let mut result = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= v;
+        }
+        i >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 * (1.0 / 4_294_967_296.0)
+}
+
+/// Reconstruction filter that weights the samples of a pixel by their offset from the pixel center.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Equal weights inside the pixel.
+    Box,
+    /// Linear falloff over a radius of one pixel.
+    Tent,
+    /// Truncated Gaussian with a radius of 1.5 pixels.
+    Gaussian,
+    /// Mitchell–Netravali cubic with `B = C = 1/3` and a radius of 2 pixels, has small negative lobes.
+    MitchellNetravali,
+}
+
+impl Filter {
+    /// Half width of the filter in pixels, samples are spread over this area around the pixel center.
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::MitchellNetravali => 2.0,
+        }
+    }
+
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    /// Maps a point of the unit square to an offset from the pixel center, distributed proportional to the absolute
+    /// filter value. The returned weight is the sign of the filter at that offset, so averaging `weight * sample`
+    /// and dividing by the sum of the weights reconstructs the pixel.
+    pub fn sample(&self, u: f64, v: f64) -> (f64, f64, f64) {
+        let (dx, sign_x) = self.sample_1d(u);
+        let (dy, sign_y) = self.sample_1d(v);
+        (dx, dy, sign_x * sign_y)
+    }
+
+    fn sample_1d(&self, u: f64) -> (f64, f64) {
+        const BINS: usize = 256;
+        static TABLES: [OnceLock<Vec<f64>>; 4] = [OnceLock::new(), OnceLock::new(), OnceLock::new(), OnceLock::new()];
+
+        let radius = self.radius();
+        let bin_width = 2.0 * radius / BINS as f64;
+        let cdf = TABLES[*self as usize].get_or_init(|| {
+            let mut cdf = Vec::with_capacity(BINS + 1);
+            cdf.push(0.0);
+            for i in 0..BINS {
+                let x = -radius + (i as f64 + 0.5) * bin_width;
+                cdf.push(cdf[i] + self.weight_1d(x).abs());
+            }
+            let total = cdf[BINS];
+            cdf.iter().map(|c| c / total).collect()
+        });
+
+        // Inverting the tabulated CDF is monotonic, so stratified patterns stay stratified.
+        let bin = cdf.partition_point(|&c| c <= u).clamp(1, BINS) - 1;
+        let width = cdf[bin + 1] - cdf[bin];
+        let t = if width > 0.0 { (u - cdf[bin]) / width } else { 0.5 };
+        let offset = -radius + (bin as f64 + t) * bin_width;
+
+        (offset, if self.weight_1d(offset) < 0.0 { -1.0 } else { 1.0 })
+    }
+
+    fn weight_1d(&self, x: f64) -> f64 {
+        let x = x.abs();
+        let radius = self.radius();
+        if x > radius {
+            return 0.0
+        }
+
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => radius - x,
+            Filter::Gaussian => {
+                const ALPHA: f64 = 2.0;
+                ((-ALPHA * x * x).exp() - (-ALPHA * radius * radius).exp()).max(0.0)
+            },
+            Filter::MitchellNetravali => {
+                const B: f64 = 1.0 / 3.0;
+                const C: f64 = 1.0 / 3.0;
+                // The cubic is defined on [-2, 2].
+                let x = 2.0 * x / radius;
+                if x < 1.0 {
+                    ((12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)) / 6.0
+                } else {
+                    ((-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)) / 6.0
+                }
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::MitchellNetravali),
+            _ => Err(format!("unknown filter `{}`, expected box, tent, gaussian or mitchell", s)),
+        }
+    }
+}
+
+/// Direction in the hemisphere around `normal` with a density of `cos / PI`.
+pub fn cosine_hemisphere(normal: Vec3, u: f64, v: f64) -> Vec3 {
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let radius = u.sqrt();
+    let phi = std::f64::consts::TAU * v;
+    Vec3::linear_combine(
+        radius * phi.cos(), &tangent,
+        radius * phi.sin(), &bitangent,
+        (1.0 - u).max(0.0).sqrt(), &normal,
+    )
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) * INV_PI
+}
+
+/// Uniformly distributed direction on the unit sphere.
+pub fn uniform_sphere(u: f64, v: f64) -> Vec3 {
+    let z = 1.0 - 2.0 * u;
+    let radius = (1.0 - z * z).max(0.0).sqrt();
+    let phi = std::f64::consts::TAU * v;
+    Vec3::new(radius * phi.cos(), radius * phi.sin(), z)
+}
+
+/// Barycentric weights of `b` and `c` for a point uniformly distributed over a triangle.
+pub fn uniform_triangle(u: f64, v: f64) -> (f64, f64) {
+    let root = u.sqrt();
+    (root * (1.0 - v), root * v)
+}
+
+/// Piecewise constant distribution over `[0, 1)` proportional to a tabulated function.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        assert!(!func.is_empty(), "distribution needs at least one value");
+        let n = func.len() as f64;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for (i, f) in func.iter().enumerate() {
+            cdf.push(cdf[i] + f.abs() / n);
+        }
+        let integral = cdf[func.len()];
+        // A function that is zero everywhere can't be sampled, fall back to a uniform distribution.
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 { *c / integral } else { i as f64 / n };
+        }
+        Self { func, cdf, integral }
+    }
+
+    /// Integral of the function over `[0, 1)`.
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    /// Continuous sample with its density, and the index of the segment it lies in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let offset = self.cdf.partition_point(|&c| c <= u).saturating_sub(1).min(self.func.len() - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 { (u - self.cdf[offset]) / width } else { 0.0 };
+        ((offset as f64 + du) / self.func.len() as f64, self.segment_pdf(offset), offset)
+    }
+
+    /// Density of [`Distribution1D::sample`] at `x`.
+    pub fn pdf(&self, x: f64) -> f64 {
+        self.segment_pdf(((x * self.func.len() as f64) as usize).min(self.func.len() - 1))
+    }
+
+    fn segment_pdf(&self, offset: usize) -> f64 {
+        if self.integral > 0.0 { self.func[offset].abs() / self.integral } else { 1.0 }
+    }
+}
+
+/// Piecewise constant distribution over the unit square, proportional to a function tabulated row by row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        assert_eq!(func.len(), width * height, "expected width * height values");
+        let rows: Vec<_> = func.chunks(width).map(|row| Distribution1D::new(row.to_vec())).collect();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::integral).collect());
+        Self { rows, marginal }
+    }
+
+    /// Point in the unit square with its density, `v` picks the row and `u` the column in it.
+    pub fn sample(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u.1);
+        let (u, pdf_u, _) = self.rows[row].sample(u.0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    /// Density of [`Distribution2D::sample`] at `(u, v)`.
+    pub fn pdf(&self, (u, v): (f64, f64)) -> f64 {
+        let row = ((v * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(v) * self.rows[row].pdf(u)
+    }
+}
+
+/// Veach's power heuristic with an exponent of two, the multiple importance sampling weight of a sample taken with
+/// the strategy of `pdf` when `other_pdf` could have produced it as well.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rng, SamplePattern};
+
+    #[test]
+    fn patterns_are_centered() {
+        let patterns = [SamplePattern::Regular, SamplePattern::Jittered, SamplePattern::Halton, SamplePattern::Sobol];
+        for pattern in patterns {
+            for count in [1, 6, 7, 12, 16] {
+                let mut rng = Rng::new(3);
+                let (mut sum_x, mut sum_y, mut n) = (0.0, 0.0, 0);
+                for _ in 0..500 {
+                    let points = pattern.generate(count, &mut rng);
+                    assert_eq!(points.len(), count);
+                    for (x, y) in points {
+                        assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y), "{:?} produced ({}, {})", pattern, x, y);
+                        sum_x += x;
+                        sum_y += y;
+                        n += 1;
+                    }
+                }
+                let (mean_x, mean_y) = (sum_x / n as f64, sum_y / n as f64);
+                assert!((mean_x - 0.5).abs() < 0.02 && (mean_y - 0.5).abs() < 0.02,
+                    "{:?} with {} samples has mean ({}, {})", pattern, count, mean_x, mean_y);
+            }
+        }
+    }
+}
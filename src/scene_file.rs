@@ -0,0 +1,1032 @@
+use std::{collections::HashMap, fmt, fs, io, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Deserializer, de::{self, MapAccess, SeqAccess, Visitor, value::MapAccessDeserializer}};
+
+use crate::{
+    camera::{Camera, Screen},
+    surface::{Scene, Surface},
+    materials::{Material, AlbedoMaterial, DiffuseMaterial, SDMaterial, SDRMaterial, DielectricMaterial, Fresnel, ConductorMaterial, Metal, PrincipledMaterial, EmissiveMaterial, HoldoutMaterial},
+    meshes::{Plane, Sphere, Quad, Triangle, TriangleMesh, MeshError},
+    light::{SceneLights, Light, DirectionalLight, PointLight, SpotLight, AreaLight},
+    background::{BackgroundSurface, EnvironmentFilter, Projection, SolidBackground, GradientBackground, HemisphereBackground, Visibility},
+    sky::Sky,
+    obj::{ObjModel, ObjError},
+    math::Vec3,
+    color::Color,
+};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: PathBuf, source: io::Error },
+    /// Malformed input, `field` is the path to the offending value, e.g. `surfaces[2].sphere.radius`.
+    Parse { path: PathBuf, line: usize, column: usize, field: String, message: String },
+    /// Well-formed input that can't be turned into a scene, like a reference to an unknown material.
+    Invalid { path: PathBuf, field: String, message: String },
+    Obj(ObjError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { path, line, column, field, message } if field.is_empty() || field == "." => {
+                write!(f, "{}:{}:{}: {}", path.display(), line, column, message)
+            },
+            SceneError::Parse { path, line, column, field, message } => {
+                write!(f, "{}:{}:{}: `{}`: {}", path.display(), line, column, field, message)
+            },
+            SceneError::Invalid { path, field, message } => write!(f, "{}: `{}`: {}", path.display(), field, message),
+            SceneError::Obj(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Obj(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(error: ObjError) -> Self {
+        SceneError::Obj(error)
+    }
+}
+
+/// Everything needed to render a scene file.
+pub struct LoadedScene {
+    pub camera: Camera,
+    pub screen: Screen,
+    pub scene: Scene,
+    pub lights: SceneLights,
+}
+
+/// Loads a JSON scene file, see [`SceneDescription`] for the format.
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<LoadedScene, SceneError> {
+    let path = path.as_ref();
+    SceneDescription::load(path)?.build(path)
+}
+
+/// Vectors are written as `[x, y, z]`.
+pub type VecDescription = [f64; 3];
+
+/// Colors are written as `[r, g, b]` with components from 0 to 255, like the `[u8; 3]` colors used in code.
+pub type ColorDescription = [f64; 3];
+
+/// Root of a scene file.
+///
+/// ```json
+/// {
+///     "camera": { "eye": [12, 12, 16], "look_at": [0, 0, 0], "screen_dist": 10, "up": [0, 1, 0] },
+///     "screen": { "width": 1920, "height": 1080, "real_width": 16, "real_height": 9 },
+///     "materials": { "red": { "sdr": { "color": [255, 0, 0], "diffuse": 1, "specular": 0.3, "exponent": 5, "reflection": 0.8 } } },
+///     "surfaces": [
+///         { "sphere": { "center": [0, 0, 0], "radius": 3, "material": "red" } },
+///         { "plane": { "point": [0, -3, 0], "normal": [0, 1, 0], "material": { "diffuse": { "color": [255, 255, 255] } } } }
+///     ],
+///     "lights": [ { "point": { "position": [0, 2, 5], "color": [0, 255, 255], "brightness": 100, "falloff": true } } ],
+///     "background": "../res/background/latitude_map.jpg"
+/// }
+/// ```
+///
+/// Relative paths are resolved against the directory of the scene file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    pub screen: ScreenDescription,
+    #[serde(default)]
+    pub materials: HashMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub surfaces: Vec<SurfaceDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    /// Rays that leave the scene see the last background in the list that is visible to them. The last one visible
+    /// to lighting is the one that lights the scene, together with its sun.
+    #[serde(default, deserialize_with = "deserialize_backgrounds")]
+    pub background: Vec<BackgroundDescription>,
+}
+
+/// Environment map, either just the path of the image or the path with settings:
+/// `{ "path": "sky.hdr", "projection": "cubemap", "intensity": 2, "rotation": 90, "filter": "bicubic" }`.
+/// A cubemap can also be given as six images in `faces` instead of a `path`, and a procedural sky with a sun as
+/// `{ "sky": { "sun_direction": [1, 1, 0], "turbidity": 3, "ground_albedo": [77, 77, 77] } }`. Plain backgrounds
+/// are a `solid` color, a vertical `gradient` or a `hemisphere` gradient. `visibility` hides the background from
+/// camera rays, reflections or lighting.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackgroundDescription {
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Cubemap faces in the order +x, -x, +y, -y, +z, -z.
+    #[serde(default)]
+    pub faces: Option<[PathBuf; 6]>,
+    #[serde(default)]
+    pub projection: ProjectionDescription,
+    #[serde(default)]
+    pub sky: Option<SkyDescription>,
+    #[serde(default)]
+    pub solid: Option<ColorDescription>,
+    #[serde(default)]
+    pub gradient: Option<GradientDescription>,
+    #[serde(default)]
+    pub hemisphere: Option<HemisphereDescription>,
+    #[serde(default)]
+    pub visibility: VisibilityDescription,
+    #[serde(default = "default_coeff")]
+    pub intensity: f64,
+    /// Rotation around the up axis in degrees.
+    #[serde(default)]
+    pub rotation: f64,
+    #[serde(default)]
+    pub filter: EnvironmentFilterDescription,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnvironmentFilterDescription {
+    Nearest,
+    #[default]
+    Bilinear,
+    Bicubic,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SkyDescription {
+    /// Direction towards the sun.
+    pub sun_direction: VecDescription,
+    #[serde(default = "default_turbidity")]
+    pub turbidity: f64,
+    #[serde(default = "default_ground_albedo")]
+    pub ground_albedo: ColorDescription,
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground_albedo() -> ColorDescription {
+    [77.0, 77.0, 77.0]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GradientDescription {
+    pub bottom: ColorDescription,
+    pub top: ColorDescription,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HemisphereDescription {
+    pub zenith: ColorDescription,
+    pub sky_horizon: ColorDescription,
+    pub ground_horizon: ColorDescription,
+    pub nadir: ColorDescription,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VisibilityDescription {
+    #[serde(default = "default_visible")]
+    pub camera: bool,
+    #[serde(default = "default_visible")]
+    pub reflections: bool,
+    #[serde(default = "default_visible")]
+    pub lighting: bool,
+}
+
+impl Default for VisibilityDescription {
+    fn default() -> Self {
+        Self { camera: true, reflections: true, lighting: true }
+    }
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectionDescription {
+    #[default]
+    Equirectangular,
+    Cubemap,
+    Angular,
+    MirrorBall,
+}
+
+impl BackgroundDescription {
+    fn from_path(path: &str) -> Self {
+        Self {
+            path: Some(PathBuf::from(path)),
+            faces: None,
+            projection: ProjectionDescription::default(),
+            sky: None,
+            solid: None,
+            gradient: None,
+            hemisphere: None,
+            visibility: VisibilityDescription::default(),
+            intensity: 1.0,
+            rotation: 0.0,
+            filter: EnvironmentFilterDescription::default(),
+        }
+    }
+
+    /// Checks that the background has exactly one source to take its radiance from.
+    fn check_source(&self) -> Result<(), String> {
+        let sources = [
+            self.path.is_some(), self.faces.is_some(), self.sky.is_some(),
+            self.solid.is_some(), self.gradient.is_some(), self.hemisphere.is_some(),
+        ];
+        if sources.into_iter().filter(|&source| source).count() != 1 {
+            return Err(String::from("needs exactly one of `path`, six `faces`, `sky`, `solid`, `gradient` or `hemisphere`"))
+        }
+        Ok(())
+    }
+
+    /// Loads the map or bakes the sky, relative paths are resolved against `base_dir`. A sky comes with its sun.
+    fn build(&self, base_dir: &Path) -> Result<(BackgroundSurface, Option<DirectionalLight>), String> {
+        self.check_source()?;
+
+        let error = |error: image::ImageError| error.to_string();
+        let path = |path: &PathBuf| base_dir.join(path).to_string_lossy().into_owned();
+        let mut sun = None;
+        let surface = if let Some(image) = &self.path {
+            let projection = match self.projection {
+                ProjectionDescription::Equirectangular => Projection::Equirectangular,
+                ProjectionDescription::Cubemap => Projection::Cubemap,
+                ProjectionDescription::Angular => Projection::Angular,
+                ProjectionDescription::MirrorBall => Projection::MirrorBall,
+            };
+            BackgroundSurface::open_with(&path(image), projection).map_err(error)?
+        } else if let Some(faces) = &self.faces {
+            let faces = faces.each_ref().map(path);
+            BackgroundSurface::open_cubemap(faces.each_ref().map(String::as_str)).map_err(error)?
+        } else if let Some(sky) = &self.sky {
+            // Turning the sun instead of the baked map keeps the sun light in line with the sky.
+            let (sin, cos) = self.rotation.to_radians().sin_cos();
+            let [x, y, z] = sky.sun_direction;
+            let sky = Sky::new(Vec3::new(x * cos + z * sin, y, z * cos - x * sin))
+                .with_turbidity(sky.turbidity)
+                .with_ground_albedo(color(sky.ground_albedo))
+                .with_intensity(self.intensity);
+            sun = Some(sky.sun());
+            sky.background()
+        } else if let Some(solid) = self.solid {
+            BackgroundSurface::from_environment(SolidBackground::new(color(solid)))
+        } else if let Some(gradient) = &self.gradient {
+            BackgroundSurface::from_environment(GradientBackground::new(color(gradient.bottom), color(gradient.top)))
+        } else if let Some(hemisphere) = &self.hemisphere {
+            BackgroundSurface::from_environment(HemisphereBackground::new(
+                color(hemisphere.zenith), color(hemisphere.sky_horizon), color(hemisphere.ground_horizon), color(hemisphere.nadir),
+            ))
+        } else {
+            unreachable!("exactly one source is set")
+        };
+
+        let surface = if self.sky.is_some() {
+            surface
+        } else {
+            surface.with_intensity(self.intensity).with_rotation(self.rotation.to_radians())
+        };
+        let filter = match self.filter {
+            EnvironmentFilterDescription::Nearest => EnvironmentFilter::Nearest,
+            EnvironmentFilterDescription::Bilinear => EnvironmentFilter::Bilinear,
+            EnvironmentFilterDescription::Bicubic => EnvironmentFilter::Bicubic,
+        };
+        let VisibilityDescription { camera, reflections, lighting } = self.visibility;
+        let surface = surface.with_filter(filter).with_visibility(Visibility { camera, reflections, lighting });
+        Ok((surface, sun.filter(|_| lighting)))
+    }
+}
+
+/// One background given as a bare path or as the full description.
+struct BackgroundEntry(BackgroundDescription);
+
+impl<'de> Deserialize<'de> for BackgroundEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(BackgroundEntryVisitor).map(BackgroundEntry)
+    }
+}
+
+struct BackgroundEntryVisitor;
+
+impl<'de> Visitor<'de> for BackgroundEntryVisitor {
+    type Value = BackgroundDescription;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a path to an image or a background")
+    }
+
+    fn visit_str<E: de::Error>(self, path: &str) -> Result<Self::Value, E> {
+        Ok(BackgroundDescription::from_path(path))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let background = BackgroundDescription::deserialize(MapAccessDeserializer::new(map))?;
+        // Checked here and not when building, so the error points at the background in the file.
+        background.check_source().map_err(de::Error::custom)?;
+        Ok(background)
+    }
+}
+
+/// Accepts a single background as well as a list of them, lists can't be nested.
+struct BackgroundVisitor;
+
+impl<'de> Visitor<'de> for BackgroundVisitor {
+    type Value = Vec<BackgroundDescription>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a path to an image, a background or a list of backgrounds")
+    }
+
+    fn visit_str<E: de::Error>(self, path: &str) -> Result<Self::Value, E> {
+        BackgroundEntryVisitor.visit_str(path).map(|background| vec![background])
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        BackgroundEntryVisitor.visit_map(map).map(|background| vec![background])
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut backgrounds = Vec::new();
+        while let Some(BackgroundEntry(background)) = seq.next_element()? {
+            backgrounds.push(background);
+        }
+        Ok(backgrounds)
+    }
+}
+
+fn deserialize_backgrounds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<BackgroundDescription>, D::Error> {
+    deserializer.deserialize_any(BackgroundVisitor)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub eye: VecDescription,
+    pub look_at: VecDescription,
+    pub screen_dist: f64,
+    #[serde(default = "default_up")]
+    pub up: VecDescription,
+}
+
+fn default_up() -> VecDescription {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScreenDescription {
+    pub width: usize,
+    pub height: usize,
+    pub real_width: f64,
+    pub real_height: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDescription {
+    Albedo {
+        color: ColorDescription,
+    },
+    Diffuse {
+        color: ColorDescription,
+        #[serde(default = "default_coeff")]
+        diffuse: f64,
+    },
+    Sd {
+        color: ColorDescription,
+        #[serde(default = "default_coeff")]
+        diffuse: f64,
+        specular: f64,
+        exponent: f64,
+    },
+    Sdr {
+        color: ColorDescription,
+        #[serde(default = "default_coeff")]
+        diffuse: f64,
+        specular: f64,
+        exponent: f64,
+        reflection: f64,
+    },
+    /// Glass, `absorption` holds Beer–Lambert coefficients per unit distance for colored glass.
+    Dielectric {
+        ior: f64,
+        #[serde(default)]
+        absorption: Option<VecDescription>,
+        #[serde(default)]
+        fresnel: FresnelDescription,
+        #[serde(default)]
+        roughness: f64,
+    },
+    /// Metal given by its complex index of refraction `eta + i k` per channel.
+    Conductor {
+        eta: VecDescription,
+        k: VecDescription,
+        #[serde(default)]
+        roughness: f64,
+    },
+    /// Conductor with the index of refraction of a known metal.
+    Metal {
+        metal: MetalDescription,
+        #[serde(default)]
+        roughness: f64,
+    },
+    /// Light source, surfaces with an emissive material light the scene through an area light.
+    Emissive {
+        color: ColorDescription,
+        #[serde(default = "default_coeff")]
+        brightness: f64,
+        #[serde(default)]
+        two_sided: bool,
+    },
+    /// Principled material with glTF style metallic-roughness parameters, `emission` is scaled by `emission_strength`.
+    Principled {
+        base_color: ColorDescription,
+        #[serde(default)]
+        metallic: f64,
+        #[serde(default = "default_principled_roughness")]
+        roughness: f64,
+        #[serde(default = "default_principled_specular")]
+        specular: f64,
+        #[serde(default)]
+        clearcoat: f64,
+        #[serde(default = "default_clearcoat_roughness")]
+        clearcoat_roughness: f64,
+        #[serde(default)]
+        sheen: f64,
+        #[serde(default = "default_principled_specular")]
+        sheen_tint: f64,
+        #[serde(default)]
+        transmission: f64,
+        #[serde(default = "default_principled_ior")]
+        ior: f64,
+        #[serde(default)]
+        emission: Option<ColorDescription>,
+        #[serde(default = "default_coeff")]
+        emission_strength: f64,
+    },
+    /// Matte object for compositing, camera rays that hit it leave the pixel transparent. Other rays see
+    /// `material`, black if none is given, so the object still casts shadows.
+    Holdout {
+        #[serde(default)]
+        material: Option<Box<MaterialDescription>>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetalDescription {
+    Gold,
+    Silver,
+    Copper,
+    #[serde(alias = "aluminum")]
+    Aluminium,
+    Iron,
+    Chromium,
+    Titanium,
+    Platinum,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FresnelDescription {
+    #[default]
+    Exact,
+    Schlick,
+}
+
+fn default_coeff() -> f64 {
+    1.0
+}
+
+fn default_principled_roughness() -> f64 {
+    0.5
+}
+
+fn default_principled_specular() -> f64 {
+    0.5
+}
+
+fn default_clearcoat_roughness() -> f64 {
+    0.03
+}
+
+fn default_principled_ior() -> f64 {
+    1.5
+}
+
+/// Either the name of an entry in `materials` or an inline material.
+#[derive(Debug, Clone)]
+pub enum MaterialRef {
+    Named(String),
+    Inline(MaterialDescription),
+}
+
+impl<'de> Deserialize<'de> for MaterialRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MaterialRefVisitor;
+
+        impl<'de> Visitor<'de> for MaterialRefVisitor {
+            type Value = MaterialRef;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a material name or a material")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+                Ok(MaterialRef::Named(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                MaterialDescription::deserialize(MapAccessDeserializer::new(map)).map(MaterialRef::Inline)
+            }
+        }
+
+        deserializer.deserialize_any(MaterialRefVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum SurfaceDescription {
+    Plane {
+        point: VecDescription,
+        normal: VecDescription,
+        material: MaterialRef,
+    },
+    Sphere {
+        center: VecDescription,
+        radius: f64,
+        material: MaterialRef,
+    },
+    /// Parallelogram with the corner `corner` and the sides `edge_u` and `edge_v`, it faces towards `edge_u x edge_v`.
+    Quad {
+        corner: VecDescription,
+        edge_u: VecDescription,
+        edge_v: VecDescription,
+        material: MaterialRef,
+    },
+    Triangle {
+        vertices: [VecDescription; 3],
+        #[serde(default)]
+        normals: Option<[VecDescription; 3]>,
+        material: MaterialRef,
+    },
+    Mesh {
+        positions: Vec<VecDescription>,
+        indices: Vec<[usize; 3]>,
+        #[serde(default)]
+        normals: Option<Vec<VecDescription>>,
+        /// Computes vertex normals from the faces if no `normals` are given.
+        #[serde(default)]
+        smooth: bool,
+        material: MaterialRef,
+    },
+    /// A Wavefront `.obj` file, its materials come from the `.mtl` files it references.
+    Obj {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum LightDescription {
+    Directional {
+        direction: VecDescription,
+        color: ColorDescription,
+        brightness: f64,
+    },
+    Point {
+        position: VecDescription,
+        color: ColorDescription,
+        brightness: f64,
+        #[serde(default)]
+        falloff: bool,
+    },
+    /// Cone angles are half angles in degrees, measured from `direction`.
+    Spot {
+        position: VecDescription,
+        direction: VecDescription,
+        color: ColorDescription,
+        brightness: f64,
+        #[serde(default = "default_inner_angle")]
+        inner_angle: f64,
+        #[serde(default = "default_outer_angle")]
+        outer_angle: f64,
+        #[serde(default)]
+        falloff: bool,
+    },
+    /// Glowing sphere, `samples` is the number of shadow rays shading casts towards it.
+    Sphere {
+        center: VecDescription,
+        radius: f64,
+        color: ColorDescription,
+        brightness: f64,
+        #[serde(default = "default_light_samples")]
+        samples: usize,
+    },
+    /// Disk that shines towards `normal`.
+    Disk {
+        center: VecDescription,
+        normal: VecDescription,
+        radius: f64,
+        color: ColorDescription,
+        brightness: f64,
+        #[serde(default = "default_light_samples")]
+        samples: usize,
+    },
+    /// Rectangle with the corner `corner` and the sides `edge_u` and `edge_v`, it shines towards `edge_u x edge_v`.
+    Rectangle {
+        corner: VecDescription,
+        edge_u: VecDescription,
+        edge_v: VecDescription,
+        color: ColorDescription,
+        brightness: f64,
+        #[serde(default = "default_light_samples")]
+        samples: usize,
+    },
+}
+
+fn default_light_samples() -> usize {
+    16
+}
+
+fn default_inner_angle() -> f64 {
+    30.0
+}
+
+fn default_outer_angle() -> f64 {
+    45.0
+}
+
+fn vec3(v: VecDescription) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+/// Whether an emission color scaled by `strength` gives off any light, black emitters would make useless lights.
+fn glows(color: ColorDescription, strength: f64) -> bool {
+    strength > 0.0 && color.iter().any(|&c| c > 0.0)
+}
+
+fn color(c: ColorDescription) -> Color {
+    Color::new(c[0] / 255.0, c[1] / 255.0, c[2] / 255.0)
+}
+
+impl MaterialDescription {
+    pub fn to_material(&self) -> Box<dyn Material> {
+        match *self {
+            MaterialDescription::Albedo { color: c } => Box::new(AlbedoMaterial::new(color(c))),
+            MaterialDescription::Diffuse { color: c, diffuse } => Box::new(DiffuseMaterial::new(color(c), diffuse)),
+            MaterialDescription::Sd { color: c, diffuse, specular, exponent } => {
+                Box::new(SDMaterial::new(color(c), diffuse, specular, exponent))
+            },
+            MaterialDescription::Sdr { color: c, diffuse, specular, exponent, reflection } => {
+                Box::new(SDRMaterial::new(color(c), diffuse, specular, exponent, reflection))
+            },
+            MaterialDescription::Dielectric { ior, absorption, fresnel, roughness } => {
+                let fresnel = match fresnel {
+                    FresnelDescription::Exact => Fresnel::Exact,
+                    FresnelDescription::Schlick => Fresnel::Schlick,
+                };
+                let mut material = DielectricMaterial::new(ior).with_fresnel(fresnel).with_roughness(roughness);
+                if let Some([r, g, b]) = absorption {
+                    material = material.with_absorption(Color::new(r, g, b));
+                }
+                Box::new(material)
+            },
+            MaterialDescription::Conductor { eta, k, roughness } => {
+                Box::new(ConductorMaterial::new(Color::new(eta[0], eta[1], eta[2]), Color::new(k[0], k[1], k[2]), roughness))
+            },
+            MaterialDescription::Metal { metal, roughness } => {
+                let metal = match metal {
+                    MetalDescription::Gold => Metal::Gold,
+                    MetalDescription::Silver => Metal::Silver,
+                    MetalDescription::Copper => Metal::Copper,
+                    MetalDescription::Aluminium => Metal::Aluminium,
+                    MetalDescription::Iron => Metal::Iron,
+                    MetalDescription::Chromium => Metal::Chromium,
+                    MetalDescription::Titanium => Metal::Titanium,
+                    MetalDescription::Platinum => Metal::Platinum,
+                };
+                Box::new(ConductorMaterial::metal(metal, roughness))
+            },
+            MaterialDescription::Emissive { color: c, brightness, two_sided } => {
+                let material = EmissiveMaterial::new(color(c), brightness);
+                Box::new(if two_sided { material.with_two_sided() } else { material })
+            },
+            MaterialDescription::Principled {
+                base_color, metallic, roughness, specular, clearcoat, clearcoat_roughness, sheen, sheen_tint, transmission, ior, emission, emission_strength,
+            } => {
+                let mut material = PrincipledMaterial::new(color(base_color))
+                    .with_metallic(metallic)
+                    .with_roughness(roughness)
+                    .with_specular(specular)
+                    .with_clearcoat(clearcoat, clearcoat_roughness)
+                    .with_sheen(sheen, sheen_tint)
+                    .with_transmission(transmission)
+                    .with_ior(ior);
+                if let Some(emission) = emission {
+                    material = material.with_emission(color(emission) * emission_strength);
+                }
+                Box::new(material)
+            },
+            MaterialDescription::Holdout { ref material } => {
+                let material = match material {
+                    Some(material) => material.to_material(),
+                    None => Box::new(AlbedoMaterial::new(0.into())),
+                };
+                Box::new(HoldoutMaterial::new(material))
+            },
+        }
+    }
+
+    /// Whether surfaces with this material give off light.
+    pub fn is_emissive(&self) -> bool {
+        match *self {
+            MaterialDescription::Emissive { color, brightness, .. } => glows(color, brightness),
+            MaterialDescription::Principled { emission, emission_strength, .. } => {
+                emission.is_some_and(|emission| glows(emission, emission_strength))
+            },
+            MaterialDescription::Holdout { ref material } => material.as_ref().is_some_and(|material| material.is_emissive()),
+            _ => false,
+        }
+    }
+}
+
+impl LightDescription {
+    /// Lights with a shape, their surfaces still have to be added to the scene, see [`AreaLight::surfaces`].
+    pub fn to_area_light(&self) -> Option<AreaLight> {
+        match *self {
+            LightDescription::Sphere { center, radius, color: c, brightness, samples } => {
+                Some(AreaLight::sphere(vec3(center), radius, color(c), brightness).with_samples(samples))
+            },
+            LightDescription::Disk { center, normal, radius, color: c, brightness, samples } => {
+                Some(AreaLight::disk(vec3(center), vec3(normal), radius, color(c), brightness).with_samples(samples))
+            },
+            LightDescription::Rectangle { corner, edge_u, edge_v, color: c, brightness, samples } => {
+                Some(AreaLight::rectangle(vec3(corner), vec3(edge_u), vec3(edge_v), color(c), brightness).with_samples(samples))
+            },
+            _ => None,
+        }
+    }
+
+    pub fn to_light(&self) -> Box<dyn Light> {
+        match *self {
+            LightDescription::Directional { direction, color: c, brightness } => {
+                Box::new(DirectionalLight::new(vec3(direction), color(c), brightness))
+            },
+            LightDescription::Point { position, color: c, brightness, falloff } => {
+                let light = PointLight::new(vec3(position), color(c), brightness);
+                Box::new(if falloff { light.with_falloff() } else { light })
+            },
+            LightDescription::Spot { position, direction, color: c, brightness, inner_angle, outer_angle, falloff } => {
+                let light = SpotLight::new(vec3(position), vec3(direction), color(c), brightness)
+                    .with_cone(inner_angle.to_radians(), outer_angle.to_radians());
+                Box::new(if falloff { light.with_falloff() } else { light })
+            },
+            LightDescription::Sphere { .. } | LightDescription::Disk { .. } | LightDescription::Rectangle { .. } => {
+                Box::new(self.to_area_light().expect("lights with a shape are area lights"))
+            },
+        }
+    }
+}
+
+impl SceneDescription {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|source| SceneError::Io { path: path.to_path_buf(), source })?;
+        Self::parse(&text, path)
+    }
+
+    /// Parses the JSON text of a scene file, `path` is only used for error messages.
+    pub fn parse(text: &str, path: &Path) -> Result<Self, SceneError> {
+        let deserializer = &mut serde_json::Deserializer::from_str(text);
+        serde_path_to_error::deserialize(deserializer).map_err(|error| {
+            let field = error.path().to_string();
+            let inner = error.into_inner();
+            let (line, column) = (inner.line(), inner.column());
+            let message = inner.to_string();
+            let message = message.trim_end_matches(&format!(" at line {} column {}", line, column)).to_string();
+            SceneError::Parse { path: path.to_path_buf(), line, column, field, message }
+        })
+    }
+
+    /// Builds the scene, `path` is the scene file that relative paths are resolved against.
+    pub fn build(&self, path: &Path) -> Result<LoadedScene, SceneError> {
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let invalid = |field: String, message: String| SceneError::Invalid { path: path.to_path_buf(), field, message };
+
+        let camera = Camera::new(
+            vec3(self.camera.eye),
+            vec3(self.camera.look_at),
+            self.camera.screen_dist,
+            vec3(self.camera.up),
+        );
+        let screen = Screen::new(self.screen.width, self.screen.height, self.screen.real_width, self.screen.real_height);
+
+        let mut scene = Scene::new();
+        let mut lights: SceneLights = Vec::new();
+        for light in &self.lights {
+            // Area lights are visible, so their surfaces go into the scene.
+            match light.to_area_light() {
+                Some(light) => {
+                    scene.extend(light.surfaces());
+                    lights.push(Box::new(light));
+                },
+                None => lights.push(light.to_light()),
+            }
+        }
+        for (index, surface) in self.surfaces.iter().enumerate() {
+            let field = |name: &str| format!("surfaces[{}].{}", index, name);
+            let mut emissive = false;
+            let mut material = |material: &MaterialRef, name: &str| -> Result<Box<dyn Material>, SceneError> {
+                let description = match material {
+                    MaterialRef::Inline(material) => material,
+                    MaterialRef::Named(material) => self.materials.get(material)
+                        .ok_or_else(|| invalid(field(&format!("{}.material", name)), format!("unknown material `{}`", material)))?,
+                };
+                emissive = description.is_emissive();
+                Ok(description.to_material())
+            };
+
+            let surfaces: Vec<Box<dyn Surface>> = match surface {
+                SurfaceDescription::Plane { point, normal, material: m } => {
+                    vec![Box::new(Plane::new(material(m, "plane")?, vec3(*point), vec3(*normal)))]
+                },
+                SurfaceDescription::Sphere { center, radius, material: m } => {
+                    vec![Box::new(Sphere::new(material(m, "sphere")?, vec3(*center), *radius))]
+                },
+                SurfaceDescription::Quad { corner, edge_u, edge_v, material: m } => {
+                    vec![Box::new(Quad::new(material(m, "quad")?, vec3(*corner), vec3(*edge_u), vec3(*edge_v)))]
+                },
+                SurfaceDescription::Triangle { vertices, normals, material: m } => {
+                    let [a, b, c] = vertices.map(vec3);
+                    let mut triangle = Triangle::new(material(m, "triangle")?, a, b, c);
+                    if let Some(normals) = normals {
+                        let [na, nb, nc] = normals.map(vec3);
+                        triangle = triangle.with_normals(na, nb, nc);
+                    }
+                    vec![Box::new(triangle)]
+                },
+                SurfaceDescription::Mesh { positions, indices, normals, smooth, material: m } => {
+                    let mut mesh = TriangleMesh::new(material(m, "mesh")?, positions.iter().copied().map(vec3).collect(), indices.clone())
+                        .map_err(|error| match error {
+                            MeshError::IndexOutOfRange { face, vertices, .. } => {
+                                invalid(field(&format!("mesh.indices[{}]", face)), format!("index out of range for {} positions", vertices))
+                            },
+                            error => invalid(field("mesh"), error.to_string()),
+                        })?;
+                    match normals {
+                        Some(normals) => {
+                            mesh = mesh.with_normals(normals.iter().copied().map(vec3).collect()).map_err(|error| match error {
+                                MeshError::LengthMismatch { expected, found, .. } => {
+                                    invalid(field("mesh.normals"), format!("expected {} normals, found {}", expected, found))
+                                },
+                                error => invalid(field("mesh"), error.to_string()),
+                            })?
+                        },
+                        None if *smooth => mesh = mesh.with_smooth_normals(),
+                        None => {},
+                    }
+                    mesh.into_surfaces()
+                },
+                SurfaceDescription::Obj { path } => {
+                    // Groups with a `Ke` material light the scene on their own, whatever the rest of the model is.
+                    let (surfaces, light) = ObjModel::load(base_dir.join(path))?.into_surfaces_and_light()?;
+                    if let Some(light) = light {
+                        scene.extend(light.surfaces());
+                        lights.push(Box::new(light));
+                    }
+                    surfaces
+                },
+            };
+
+            // Emissive surfaces light the scene through an area light that samples them.
+            if emissive && surfaces.iter().any(|surface| surface.area().is_some()) {
+                let light = AreaLight::new(surfaces);
+                scene.extend(light.surfaces());
+                lights.push(Box::new(light));
+            } else {
+                scene.extend(surfaces);
+            }
+        }
+
+        // Lighting rays only ever see the last background visible to them, so only that one lights the scene.
+        let lighting = self.background.iter().rposition(|background| background.visibility.lighting);
+        for (index, background) in self.background.iter().enumerate() {
+            let name = if self.background.len() == 1 { String::from("background") } else { format!("background[{}]", index) };
+            let (surface, sun) = background.build(base_dir).map_err(|message| invalid(name, message))?;
+            if lighting == Some(index) {
+                if let Some(sun) = sun {
+                    lights.push(Box::new(sun));
+                }
+                lights.push(Box::new(surface.light()));
+            }
+            scene.push(Box::new(surface));
+        }
+
+        Ok(LoadedScene { camera, screen, scene, lights })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Scene file with the surfaces and backgrounds given, each of them on a line of its own starting at line 4.
+    fn scene(surfaces: &[&str], background: &str) -> String {
+        format!(
+            "{{\n\"camera\": {{ \"eye\": [0, 0, 10], \"look_at\": [0, 0, 0], \"screen_dist\": 10 }},\n\
+             \"screen\": {{ \"width\": 4, \"height\": 4, \"real_width\": 1, \"real_height\": 1 }},\n\
+             \"surfaces\": [\n{}\n],\n\"background\": {}\n}}",
+            surfaces.join(",\n"), background,
+        )
+    }
+
+    fn parse_error(text: &str) -> String {
+        match SceneDescription::parse(text, Path::new("scene.json")) {
+            Ok(_) => panic!("parsed invalid scene:\n{}", text),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn unknown_field() {
+        let sphere = r#"{ "sphere": { "center": [0, 0, 0], "radius": 1, "radius2": 2, "material": "red" } }"#;
+        let error = parse_error(&scene(&[sphere], "[]"));
+        assert!(error.starts_with("scene.json:5:"), "{}", error);
+        assert!(error.contains("`surfaces[0].sphere.radius2`"), "{}", error);
+        assert!(error.contains("unknown field `radius2`"), "{}", error);
+    }
+
+    #[test]
+    fn wrong_type() {
+        let plane = r#"{ "plane": { "point": [0, 0, 0], "normal": [0, 1, 0], "material": "red" } }"#;
+        let sphere = r#"{ "sphere": { "center": [0, 0, 0], "radius": "large", "material": "red" } }"#;
+        let error = parse_error(&scene(&[plane, sphere], "[]"));
+        assert!(error.starts_with("scene.json:6:"), "{}", error);
+        assert!(error.contains("`surfaces[1].sphere.radius`"), "{}", error);
+        assert!(error.contains("invalid type: string \"large\", expected f64"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_material() {
+        let sphere = r#"{ "sphere": { "center": [0, 0, 0], "radius": 1, "material": "red" } }"#;
+        let path = Path::new("scene.json");
+        let description = SceneDescription::parse(&scene(&[sphere], "[]"), path).unwrap();
+        let error = description.build(path).err().expect("built scene with unknown material").to_string();
+        assert_eq!(error, "scene.json: `surfaces[0].sphere.material`: unknown material `red`");
+    }
+
+    #[test]
+    fn conflicting_background_sources() {
+        let background = "[\n\"sky.hdr\",\n{ \"solid\": [0, 0, 0], \"gradient\": { \"bottom\": [0, 0, 0], \"top\": [255, 255, 255] } }\n]";
+        let error = parse_error(&scene(&[], background));
+        assert!(error.starts_with("scene.json:9:"), "{}", error);
+        assert!(error.contains("`background[1]`"), "{}", error);
+        assert!(error.contains("needs exactly one of"), "{}", error);
+    }
+
+    #[test]
+    fn nested_background_list() {
+        let error = parse_error(&scene(&[], "[\n[]\n]"));
+        assert!(error.starts_with("scene.json:8:"), "{}", error);
+        assert!(error.contains("`background[0]`"), "{}", error);
+        assert!(error.contains("invalid type: sequence, expected a path to an image or a background"), "{}", error);
+    }
+
+    #[test]
+    fn only_the_last_lighting_background_lights() {
+        let lights = |background: &str| {
+            let path = Path::new("scene.json");
+            let description = SceneDescription::parse(&scene(&[], background), path).unwrap();
+            description.build(path).unwrap().lights.len()
+        };
+        let sky = r#"{ "sky": { "sun_direction": [1, 1, 0] } }"#;
+        let solid = r#"{ "solid": [10, 20, 30] }"#;
+        let hidden = r#"{ "solid": [10, 20, 30], "visibility": { "lighting": false } }"#;
+
+        assert_eq!(lights(&format!("[{}, {}]", solid, solid)), 1);
+        // The sky comes with its sun, but only when it is the one lighting the scene.
+        assert_eq!(lights(&format!("[{}, {}]", solid, sky)), 2);
+        assert_eq!(lights(&format!("[{}, {}]", sky, solid)), 1);
+        assert_eq!(lights(&format!("[{}, {}]", sky, hidden)), 2);
+        assert_eq!(lights(&format!("[{}, {}]", hidden, hidden)), 0);
+    }
+
+    #[test]
+    fn black_emission_is_no_light() {
+        let material: MaterialDescription = serde_json::from_str(r#"{ "principled": { "base_color": [255, 255, 255], "emission": [0, 0, 0] } }"#).unwrap();
+        assert!(!material.is_emissive());
+        let material: MaterialDescription = serde_json::from_str(r#"{ "principled": { "base_color": [255, 255, 255], "emission": [255, 0, 0] } }"#).unwrap();
+        assert!(material.is_emissive());
+    }
+}
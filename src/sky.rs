@@ -0,0 +1,253 @@
+use crate::{math::Vec3, color::Color, light::DirectionalLight, background::BackgroundSurface};
+
+/// Resolution of the environment map the sky is baked into.
+const SKY_MAP_SIZE: (u32, u32) = (1024, 512);
+
+/// Illuminance of the sun outside the atmosphere in klux.
+const SOLAR_ILLUMINANCE: f64 = 128.0;
+
+/// The model gives luminance in kcd/m², this brings a white diffuse surface under a high sun to just below one.
+const SKY_SCALE: f64 = 1.0 / 40.0;
+
+/// Wavelengths in micrometers that stand in for the red, green and blue channels when attenuating sunlight.
+const CHANNEL_WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+/// Clear sky after Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight" (1999).
+///
+/// The sky is lit by a sun in `sun_direction`, `turbidity` says how hazy the air is, from about 2 for a very clear
+/// day to 10 for a hazy one. Below the horizon is a diffuse ground with the given albedo, lit by sky and sun.
+/// Use [`Sky::background`] together with [`Sky::sun`], the sun disk itself is left out of the background.
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    sun_direction: Vec3,
+    turbidity: f64,
+    ground_albedo: Color,
+    intensity: f64,
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3) -> Self {
+        Self {
+            sun_direction: sun_direction.normalize(),
+            turbidity: 3.0,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            intensity: 1.0,
+        }
+    }
+
+    /// Sets the haziness, clamped to the range of 2 to 10 the model was fitted for.
+    pub fn with_turbidity(mut self, turbidity: f64) -> Self {
+        self.turbidity = turbidity.clamp(2.0, 10.0);
+        self
+    }
+
+    pub fn with_ground_albedo(mut self, albedo: Color) -> Self {
+        self.ground_albedo = albedo;
+        self
+    }
+
+    /// Scales sky and sun together.
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Radiance that a ray travelling in `direction` sees, without the sun disk.
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        self.model().radiance(direction)
+    }
+
+    /// The sun as a light, with the color sunlight has after passing through the atmosphere. It goes dark once the
+    /// sun sets.
+    pub fn sun(&self) -> DirectionalLight {
+        DirectionalLight::new(self.sun_direction, self.sun_irradiance() * (SKY_SCALE * self.intensity), 1.0)
+    }
+
+    /// The sky baked into an environment map, add its [`BackgroundSurface::light`] to light the scene with it.
+    pub fn background(&self) -> BackgroundSurface {
+        let model = self.model();
+        BackgroundSurface::from_fn(SKY_MAP_SIZE.0, SKY_MAP_SIZE.1, |direction| model.radiance(direction))
+    }
+
+    /// Irradiance in klux from the sun on a surface facing it, after Rayleigh scattering and scattering by aerosols
+    /// along the path through the atmosphere.
+    fn sun_irradiance(&self) -> Color {
+        let cos_theta = self.sun_direction.y;
+        if cos_theta <= 0.0 {
+            return 0.into()
+        }
+        let theta = cos_theta.acos().to_degrees();
+        // Relative optical mass, how much more air the light passes than from straight above.
+        let mass = 1.0 / (cos_theta + 0.15 * (93.885 - theta).powf(-1.253));
+        // Ångström's turbidity formula with a wavelength exponent of 1.3.
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let [r, g, b] = CHANNEL_WAVELENGTHS.map(|lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            SOLAR_ILLUMINANCE * rayleigh * aerosol
+        });
+        Color::new(r, g, b)
+    }
+
+    fn model(&self) -> SkyModel {
+        let t = self.turbidity;
+        // The model only covers suns above the horizon, a set sun leaves the sky of a sunset.
+        let sun = Vec3::new(self.sun_direction.x, self.sun_direction.y.max(0.0), self.sun_direction.z).normalize();
+        let theta_s = sun.y.clamp(-1.0, 1.0).acos();
+        let (theta2, theta3) = (theta_s * theta_s, theta_s * theta_s * theta_s);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (std::f64::consts::PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let zenith_x = t * t * (0.00166 * theta3 - 0.00375 * theta2 + 0.00209 * theta_s)
+            + t * (-0.02903 * theta3 + 0.06377 * theta2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta3 - 0.21196 * theta2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = t * t * (0.00275 * theta3 - 0.00610 * theta2 + 0.00317 * theta_s)
+            + t * (-0.04214 * theta3 + 0.08970 * theta2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta3 - 0.26756 * theta2 + 0.06670 * theta_s + 0.26688);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+        let zenith = [zenith_luminance.max(0.0), zenith_x, zenith_y];
+        // Dividing by the distribution at the zenith makes it hit the zenith values exactly.
+        let scale = [0, 1, 2].map(|i| zenith[i] / perez_distribution(perez[i], 1.0, theta_s));
+
+        let mut model = SkyModel { sun, perez, scale, ground: 0.into(), intensity: SKY_SCALE * self.intensity };
+        model.ground = self.ground_albedo * (model.sky_irradiance() + self.sun_irradiance() * sun.y.max(0.0))
+            * (std::f64::consts::FRAC_1_PI * model.intensity);
+        model
+    }
+}
+
+/// Perez et al. luminance distribution for a view direction `cos_theta` away from the zenith and `gamma` radians
+/// away from the sun.
+fn perez_distribution([a, b, c, d, e]: [f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let cos_gamma = gamma.cos();
+    (1.0 + a * (b / cos_theta.max(1e-3)).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+/// The coefficients of a [`Sky`], worked out once for all directions.
+struct SkyModel {
+    sun: Vec3,
+    /// Distribution coefficients for the luminance Y and the chromaticities x and y.
+    perez: [[f64; 5]; 3],
+    scale: [f64; 3],
+    /// Radiance of the ground, already scaled by the intensity.
+    ground: Color,
+    intensity: f64,
+}
+
+impl SkyModel {
+    fn radiance(&self, direction: Vec3) -> Color {
+        let direction = direction.normalize();
+        if direction.y < 0.0 {
+            return self.ground
+        }
+        self.sky(direction) * self.intensity
+    }
+
+    /// Radiance of the sky in kcd/m².
+    fn sky(&self, direction: Vec3) -> Color {
+        let gamma = (direction * self.sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.scale[i] * perez_distribution(self.perez[i], direction.y, gamma));
+        xyy_to_rgb(x, y, luminance)
+    }
+
+    /// Irradiance in klux the sky alone casts onto the ground.
+    fn sky_irradiance(&self) -> Color {
+        const STEPS: (usize, usize) = (32, 64);
+        let mut irradiance: Color = 0.into();
+        // Uniform steps in the cosine of the zenith angle are uniform steps in solid angle.
+        for i in 0..STEPS.0 {
+            let cos_theta = (i as f64 + 0.5) / STEPS.0 as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..STEPS.1 {
+                let phi = std::f64::consts::TAU * (j as f64 + 0.5) / STEPS.1 as f64;
+                let direction = Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                irradiance = irradiance + self.sky(direction) * cos_theta;
+            }
+        }
+        irradiance * (std::f64::consts::TAU / (STEPS.0 * STEPS.1) as f64)
+    }
+}
+
+/// Converts CIE xyY into linear sRGB.
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return 0.into()
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Color::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::Light;
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() <= tolerance * b.abs(), "{} != {}", a, b);
+    }
+
+    fn sun_color(sky: &Sky) -> Color {
+        sky.sun().color(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn zenith_luminance() {
+        for (elevation, turbidity) in [(60.0f64, 2.0), (30.0, 3.0), (10.0, 6.0)] {
+            let elevation = elevation.to_radians();
+            let sky = Sky::new(Vec3::new(elevation.cos(), elevation.sin(), 0.0)).with_turbidity(turbidity);
+            let theta_s = std::f64::consts::FRAC_PI_2 - elevation;
+            let chi = (4.0 / 9.0 - turbidity / 120.0) * (std::f64::consts::PI - 2.0 * theta_s);
+            let zenith = (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192;
+            let radiance = sky.radiance(Vec3::new(0.0, 1.0, 0.0));
+            assert_close(radiance.luminance(), zenith * SKY_SCALE, 1e-3);
+        }
+    }
+
+    #[test]
+    fn sun_sets() {
+        assert!(sun_color(&Sky::new(Vec3::new(1.0, 0.1, 0.0))).luminance() > 0.0);
+        for y in [0.0, -0.3] {
+            let sky = Sky::new(Vec3::new(1.0, y, 0.0));
+            let color = sun_color(&sky);
+            assert_eq!([color.r, color.g, color.b], [0.0; 3], "sun at y = {}", y);
+            // The sky of a sunset stays.
+            assert!(sky.radiance(Vec3::new(0.0, 1.0, 0.0)).luminance() > 0.0);
+        }
+    }
+
+    #[test]
+    fn turbidity_is_clamped() {
+        let sun = Vec3::new(1.0, 1.0, 0.0);
+        assert_eq!(Sky::new(sun).with_turbidity(0.5).turbidity, 2.0);
+        assert_eq!(Sky::new(sun).with_turbidity(5.0).turbidity, 5.0);
+        assert_eq!(Sky::new(sun).with_turbidity(20.0).turbidity, 10.0);
+    }
+
+    #[test]
+    fn intensity_scales_sky_ground_and_sun() {
+        let sky = Sky::new(Vec3::new(0.3, 0.8, -0.5));
+        let bright = sky.with_intensity(2.5);
+        for direction in [Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.5, 0.2, 0.1), Vec3::new(0.0, -1.0, 0.0)] {
+            let (a, b) = (sky.radiance(direction), bright.radiance(direction));
+            for (a, b) in [(a.r, b.r), (a.g, b.g), (a.b, b.b)] {
+                assert!(a > 0.0);
+                assert_close(b, 2.5 * a, 1e-9);
+            }
+        }
+        let (a, b) = (sun_color(&sky), sun_color(&bright));
+        for (a, b) in [(a.r, b.r), (a.g, b.g), (a.b, b.b)] {
+            assert!(a > 0.0);
+            assert_close(b, 2.5 * a, 1e-9);
+        }
+    }
+}
@@ -1,25 +1,232 @@
-use crate::{math::{Ray, Vec3}, materials::Material, color::Color, light::SceneLights};
+use std::sync::{Arc, OnceLock};
 
-pub type Scene = Vec<Box<dyn Surface>>;
+use crate::{math::{Ray, Vec3, Aabb}, materials::Material, color::Color, light::SceneLights, bvh::Bvh};
 
-pub trait Surface {
+pub trait Surface: Send + Sync {
+    fn get_material(&self) -> &dyn Material;
 
-    fn hit(&self, ray: &Ray, scene: &Scene, lights: Option<&SceneLights>, bounce_count: usize, min_distance: f64) -> Option<HitInfo> {
-        if let Some(hit) = self.surface_hit(ray, min_distance) {
-            if (hit - ray.origin).mag() <= min_distance {
-                return self.get_material().calc_mat(ray, hit, self.get_normal(&hit), scene, lights, bounce_count)
+    /// Closest intersection with the ray that is nearer than `max_distance`, without shading it.
+    fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<Intersection>;
+
+    fn shadow_hit(&self, ray: &Ray, light_dist: f64) -> bool;
+
+    /// Bounding box of the surface, `None` for unbounded surfaces like planes or backgrounds.
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// Surface area, `None` for surfaces that can't be sampled like planes or backgrounds.
+    fn area(&self) -> Option<f64> {
+        None
+    }
+
+    /// Uniformly distributed point on the surface and the normal there, `u` is a uniform point in the unit square.
+    fn sample_point(&self, _u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        None
+    }
+
+    /// Picks a point on the surface to light `origin` from, with its solid angle density as seen from `origin`.
+    /// Points are uniformly distributed by area unless the surface knows which part of it `origin` can see.
+    fn sample_from(&self, origin: Vec3, u: (f64, f64)) -> Option<SurfaceSample> {
+        let area = self.area()?;
+        let (position, normal) = self.sample_point(u)?;
+        let offset = position - origin;
+        let distance_sqr = offset.mag2();
+        let cos = (normal * offset).abs() / distance_sqr.sqrt();
+        (distance_sqr > 0.0 && cos > 0.0).then(|| SurfaceSample { position, normal, pdf: distance_sqr / (cos * area) })
+    }
+
+    /// Solid angle density with which [`Surface::sample_from`] picks the point `hit` from `origin`.
+    fn pdf_from(&self, origin: Vec3, hit: &Intersection) -> f64 {
+        let Some(area) = self.area() else { return 0.0 };
+        let offset = hit.position - origin;
+        let distance_sqr = offset.mag2();
+        let cos = (hit.normal * offset).abs() / distance_sqr.sqrt();
+        if cos > 0.0 { distance_sqr / (cos * area) } else { 0.0 }
+    }
+}
+
+/// Point on a surface picked by [`Surface::sample_from`].
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub position: Vec3,
+    pub normal: Vec3,
+    /// Solid angle density as seen from the shaded point.
+    pub pdf: f64,
+}
+
+/// Shared surfaces, so a light can sample the same geometry that is added to the scene.
+impl<S: Surface + ?Sized> Surface for Arc<S> {
+    fn get_material(&self) -> &dyn Material {
+        self.as_ref().get_material()
+    }
+
+    fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<Intersection> {
+        self.as_ref().intersect(ray, max_distance)
+    }
+
+    fn shadow_hit(&self, ray: &Ray, light_dist: f64) -> bool {
+        self.as_ref().shadow_hit(ray, light_dist)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.as_ref().bounds()
+    }
+
+    fn area(&self) -> Option<f64> {
+        self.as_ref().area()
+    }
+
+    fn sample_point(&self, u: (f64, f64)) -> Option<(Vec3, Vec3)> {
+        self.as_ref().sample_point(u)
+    }
+
+    fn sample_from(&self, origin: Vec3, u: (f64, f64)) -> Option<SurfaceSample> {
+        self.as_ref().sample_from(origin, u)
+    }
+
+    fn pdf_from(&self, origin: Vec3, hit: &Intersection) -> f64 {
+        self.as_ref().pdf_from(origin, hit)
+    }
+}
+
+/// All surfaces of a scene. Bounded surfaces are put into a [`Bvh`] the first time the scene is queried,
+/// unbounded surfaces are tested one after another in the order they were added.
+#[derive(Default)]
+pub struct Scene {
+    surfaces: Vec<Box<dyn Surface>>,
+    accel: OnceLock<SceneAccel>,
+}
+
+struct SceneAccel {
+    bvh: Bvh,
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, surface: Box<dyn Surface>) {
+        self.surfaces.push(surface);
+        self.accel = OnceLock::new();
+    }
+
+    pub fn len(&self) -> usize {
+        self.surfaces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.surfaces.is_empty()
+    }
+
+    pub fn surfaces(&self) -> &[Box<dyn Surface>] {
+        &self.surfaces
+    }
+
+    fn accel(&self) -> &SceneAccel {
+        self.accel.get_or_init(|| {
+            let mut bounded = Vec::new();
+            let mut bounds = Vec::new();
+            let mut unbounded = Vec::new();
+            for (index, surface) in self.surfaces.iter().enumerate() {
+                match surface.bounds() {
+                    Some(aabb) => {
+                        bounded.push(index);
+                        bounds.push(aabb);
+                    },
+                    None => unbounded.push(index),
+                }
+            }
+            SceneAccel { bvh: Bvh::build(&bounds), bounded, unbounded }
+        })
+    }
+
+    /// Finds the closest surface along the ray without shading it.
+    pub fn intersect(&self, ray: &Ray, max_distance: f64) -> Option<Intersection> {
+        let accel = self.accel();
+        let mut closest: Option<Intersection> = None;
+        let mut min_distance = max_distance;
+
+        // Every hit reported to the BVH is closer than the previous one, so the last one recorded is the closest.
+        if let Some((_, distance)) = accel.bvh.closest_hit(ray, min_distance, |primitive, min_distance| {
+            let index = accel.bounded[primitive];
+            let hit = self.surfaces[index].intersect(ray, min_distance)?;
+            closest = Some(Intersection { surface: index, ..hit });
+            Some(hit.t)
+        }) {
+            min_distance = distance;
+        }
+
+        for &index in &accel.unbounded {
+            if let Some(hit) = self.surfaces[index].intersect(ray, min_distance) {
+                min_distance = hit.t;
+                closest = Some(Intersection { surface: index, ..hit });
             }
         }
-        None
+
+        closest
     }
 
-    fn get_normal(&self, hit: &Vec3) -> Vec3;
+    /// Returns true if any surface blocks the ray before `max_distance`.
+    pub fn occluded(&self, ray: &Ray, max_distance: f64) -> bool {
+        let accel = self.accel();
+        accel.bvh.any_hit(ray, max_distance, |primitive| self.surfaces[accel.bounded[primitive]].shadow_hit(ray, max_distance))
+            || accel.unbounded.iter().any(|&index| self.surfaces[index].shadow_hit(ray, max_distance))
+    }
 
-    fn get_material(&self) -> &Box<dyn Material>;
+    /// Shades the closest surface along the ray, surfaces behind it are never shaded.
+    pub fn hit(&self, ray: &Ray, lights: Option<&SceneLights>, bounce_count: usize) -> Option<HitInfo> {
+        let hit = self.intersect(ray, f64::INFINITY)?;
+        self.surfaces[hit.surface].get_material().calc_mat(ray, hit.position, hit.normal, self, lights, bounce_count)
+    }
+}
+
+impl From<Vec<Box<dyn Surface>>> for Scene {
+    fn from(surfaces: Vec<Box<dyn Surface>>) -> Self {
+        Self { surfaces, accel: OnceLock::new() }
+    }
+}
 
-    fn surface_hit(&self, ray: &Ray, min_distance: f64) -> Option<Vec3>;
+impl FromIterator<Box<dyn Surface>> for Scene {
+    fn from_iter<T: IntoIterator<Item = Box<dyn Surface>>>(iter: T) -> Self {
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
 
-    fn shadow_hit(&self, ray: &Ray, light_dist: f64) -> bool;
+impl Extend<Box<dyn Surface>> for Scene {
+    fn extend<T: IntoIterator<Item = Box<dyn Surface>>>(&mut self, iter: T) {
+        self.surfaces.extend(iter);
+        self.accel = OnceLock::new();
+    }
+}
+
+/// Where a ray hits a surface, see [`Surface::intersect`] and [`Scene::intersect`].
+#[derive(Debug, Clone, Copy)]
+pub struct Intersection {
+    /// Distance along the ray, infinite for backgrounds.
+    pub t: f64,
+    /// Hit point, backgrounds use the ray direction since the point lies at infinity.
+    pub position: Vec3,
+    /// Shading normal, interpolated for smooth triangles.
+    pub normal: Vec3,
+    /// Surface parameterization at the hit point, if the surface has one.
+    pub uv: Option<(f64, f64)>,
+    /// Index of the surface in the scene, filled in by [`Scene::intersect`].
+    pub surface: usize,
+}
+
+impl Intersection {
+    pub fn new(ray: &Ray, t: f64, normal: Vec3) -> Self {
+        Self { t, position: ray.at(t), normal, uv: None, surface: 0 }
+    }
+
+    pub fn with_uv(mut self, uv: (f64, f64)) -> Self {
+        self.uv = Some(uv);
+        self
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -0,0 +1,212 @@
+use crate::color::Color;
+
+/// Operator that compresses linear radiance into the displayable `[0, 1]` range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    /// Clamps every channel, everything above 1 turns white.
+    Clamp,
+    /// `L / (1 + L)` on the luminance, never reaches white.
+    Reinhard,
+    /// Reinhard with a white point, luminance at or above `white` maps to 1.
+    ExtendedReinhard { white: f64 },
+    /// Krzysztof Narkowicz's fit of the ACES filmic reference rendering transform.
+    AcesFilmic,
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+}
+
+impl ToneMap {
+    pub fn apply(&self, color: Color) -> Color {
+        match *self {
+            ToneMap::Clamp => color.map(|c| c.clamp(0.0, 1.0)),
+            ToneMap::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMap::ExtendedReinhard { white } => {
+                let inv_white_sqr = 1.0 / (white * white);
+                scale_luminance(color, |l| l * (1.0 + l * inv_white_sqr) / (1.0 + l))
+            },
+            ToneMap::AcesFilmic => color.map(|c| {
+                let (a, b, c2, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                let c = c.max(0.0);
+                ((c * (a * c + b)) / (c * (c2 * c + d) + e)).clamp(0.0, 1.0)
+            }),
+            ToneMap::Hable => {
+                const EXPOSURE_BIAS: f64 = 2.0;
+                const WHITE: f64 = 11.2;
+                let white_scale = 1.0 / hable_partial(WHITE);
+                color.map(|c| (hable_partial(c.max(0.0) * EXPOSURE_BIAS) * white_scale).clamp(0.0, 1.0))
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" | "none" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::AcesFilmic),
+            "hable" | "uncharted2" => Ok(ToneMap::Hable),
+            _ => match s.strip_prefix("reinhard:").map(str::parse::<f64>) {
+                Some(Ok(white)) if white > 0.0 && white.is_finite() => Ok(ToneMap::ExtendedReinhard { white }),
+                Some(Ok(white)) => Err(format!("white point of `{}` must be a positive number, got {}", s, white)),
+                _ => Err(format!("unknown tone mapping `{}`, expected clamp, reinhard, reinhard:<white>, aces or hable", s)),
+            },
+        }
+    }
+}
+
+fn scale_luminance<F: Fn(f64) -> f64>(color: Color, curve: F) -> Color {
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0)
+    }
+    (color * (curve(luminance) / luminance)).map(|c| c.clamp(0.0, 1.0))
+}
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+/// sRGB transfer function, encodes a linear value for display.
+pub fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of [`linear_to_srgb`].
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// How linear radiance is turned into display values when exporting low dynamic range images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExportSettings {
+    /// Exposure adjustment in stops, every stop doubles the brightness.
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+    /// Encode with the sRGB transfer function, otherwise the linear values are written as they are.
+    pub srgb: bool,
+    /// Write an alpha channel where the format has one, transparent where camera rays missed or hit holdouts.
+    pub alpha: bool,
+}
+
+impl ExportSettings {
+    /// Plain clamped linear output, which the examples write. The command line turns sRGB encoding on.
+    pub fn new() -> Self {
+        Self { exposure: 0.0, tone_map: ToneMap::Clamp, srgb: false, alpha: false }
+    }
+
+    pub fn with_exposure(mut self, exposure: f64) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn with_tone_map(mut self, tone_map: ToneMap) -> Self {
+        self.tone_map = tone_map;
+        self
+    }
+
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    pub fn with_alpha(mut self, alpha: bool) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    /// Applies exposure, tone mapping and the transfer function, the result is in `[0, 1]`.
+    pub fn to_display(&self, color: Color) -> Color {
+        let color = self.tone_map.apply(color * 2f64.powf(self.exposure));
+        if self.srgb {
+            color.map(linear_to_srgb)
+        } else {
+            color
+        }
+    }
+
+    pub fn to_bytes(&self, color: Color) -> [u8; 3] {
+        let color = self.to_display(color);
+        [color.r, color.g, color.b].map(|c| (c * 255.0).round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(c: f64) -> Color {
+        Color::new(c, c, c)
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=1000 {
+            let c = i as f64 / 1000.0;
+            assert!((srgb_to_linear(linear_to_srgb(c)) - c).abs() < 1e-12, "linear {}", c);
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-12, "encoded {}", c);
+        }
+        // The linear segment and the power curve meet at the breakpoints.
+        let (linear, encoded) = (0.0031308, 0.04045);
+        assert!((linear_to_srgb(linear) - encoded).abs() < 1e-5);
+        assert!((linear_to_srgb(linear + 1e-12) - linear_to_srgb(linear)).abs() < 1e-6);
+        assert!((srgb_to_linear(encoded) - linear).abs() < 1e-6);
+        assert!((srgb_to_linear(encoded + 1e-12) - srgb_to_linear(encoded)).abs() < 1e-6);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn reinhard_white_point() {
+        for white in [0.5, 1.0, 4.0, 100.0] {
+            let tone_map = ToneMap::ExtendedReinhard { white };
+            assert!((tone_map.apply(gray(white)).g - 1.0).abs() < 1e-12, "white {}", white);
+            assert!(tone_map.apply(gray(0.99 * white)).g < 1.0, "white {}", white);
+        }
+        for c in [0.5, 1.0, 10.0, 1000.0] {
+            let mapped = ToneMap::Reinhard.apply(gray(c));
+            assert!(mapped.r < 1.0 && mapped.g < 1.0 && mapped.b < 1.0, "{} maps to {:?}", c, mapped);
+        }
+    }
+
+    #[test]
+    fn filmic_curves_are_monotonic() {
+        for tone_map in [ToneMap::AcesFilmic, ToneMap::Hable] {
+            assert_eq!(tone_map.apply(gray(-1.0)).g, 0.0);
+            let mut previous = tone_map.apply(gray(0.0)).g;
+            assert_eq!(previous, 0.0);
+            for i in 1..=2000 {
+                let mapped = tone_map.apply(gray(i as f64 / 20.0)).g;
+                assert!(mapped >= previous && mapped <= 1.0, "{:?} at {}: {}", tone_map, i as f64 / 20.0, mapped);
+                previous = mapped;
+            }
+            assert_eq!(previous, 1.0, "{:?} saturates", tone_map);
+        }
+    }
+
+    #[test]
+    fn parse_tone_maps() {
+        assert_eq!("reinhard".parse(), Ok(ToneMap::Reinhard));
+        assert_eq!("reinhard:4".parse(), Ok(ToneMap::ExtendedReinhard { white: 4.0 }));
+        assert_eq!("uncharted2".parse(), Ok(ToneMap::Hable));
+        for invalid in ["reinhard:0", "reinhard:-1", "reinhard:inf", "reinhard:", "filmic"] {
+            assert!(invalid.parse::<ToneMap>().is_err(), "{} parsed", invalid);
+        }
+    }
+}